@@ -1,9 +1,17 @@
-use std::{time::Instant, collections::HashMap};
+use std::{collections::HashMap, ops::Bound, sync::Arc, time::SystemTime};
+
+use immutable_chunkmap::map::Map;
 
 use crate::{
     datom::Datom,
-    indexes::{AEVTIndex, EAVTIndex},
-    AttributeId, EntityId, TransactionId, V, Key, pull::Pattern,
+    error::{Error, Result},
+    indexes::{current, AEVTIndex, AVETIndex, EAVTIndex, VAETIndex},
+    partition,
+    pull::{self, Pattern},
+    query::{self, Arg, Query, QueryResult},
+    schema::{self, Attribute},
+    transaction::{self, EntityRef, TxFn, TxOp, TxReport},
+    EntityId, Key, TransactionId, SIZE, V,
 };
 
 #[derive(Clone)]
@@ -11,8 +19,19 @@ use crate::{
 pub struct DatabaseSnapshot {
     eavt: EAVTIndex,
     aevt: AEVTIndex,
-    // idents: Map<EntityId, Key, SIZE>,
-    // attributes: Map<EntityId, Attribute, SIZE>
+    avet: AVETIndex,
+    vaet: VAETIndex,
+    basis_t: TransactionId,
+    // For each partition, one past the greatest index in use within it, which
+    // is where new entities are allocated from.
+    partitions: Map<EntityId, i64, SIZE>,
+    // When set, only datoms with `t <= as_of` are visible.
+    as_of: Option<TransactionId>,
+    idents: Map<Key, EntityId, SIZE>,
+    attributes: Map<EntityId, Attribute, SIZE>,
+    // The transaction functions installed, by the entity naming them and the
+    // transaction that installed them.
+    functions: Map<(EntityId, TransactionId), TxFn, SIZE>,
 }
 
 // TODO: This is some overload-like behavior in Eva that has a 
@@ -20,7 +39,7 @@ pub struct DatabaseSnapshot {
 // the flexibility here is. In usage, it's a lot like "Into"/"From", 
 // but the coercion to the desired type requires a snapshot.
 pub enum Time {
-    Instant(Instant),
+    Instant(SystemTime),
     TransactionId(TransactionId),
 }
 
@@ -28,116 +47,557 @@ pub enum Time {
 // clunky translation. I'm not sure what the "best" way to represent
 // the flexibility here is. In usage, it's a lot like "Into"/"From", 
 // but the coercion to the desired type requires a snapshot.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Identity {
     EntityId(EntityId),
     LookupRef(LookupRef),
     Keyword(Key),
 }
 
-pub struct Attribute {}
-pub struct LookupRef {}
+// Identifies the entity holding `value` for the unique attribute `attr`, e.g.
+// `[:user/email "ada@example.com"]`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LookupRef {
+    pub attr: Key,
+    pub value: V,
+}
+
+impl LookupRef {
+    pub fn new(attr: Key, value: V) -> LookupRef {
+        LookupRef { attr, value }
+    }
+}
+impl From<EntityId> for Identity {
+    fn from(e: EntityId) -> Identity {
+        Identity::EntityId(e)
+    }
+}
+
+impl From<Key> for Identity {
+    fn from(key: Key) -> Identity {
+        Identity::Keyword(key)
+    }
+}
+
+impl From<LookupRef> for Identity {
+    fn from(lookup_ref: LookupRef) -> Identity {
+        Identity::LookupRef(lookup_ref)
+    }
+}
+
 pub struct Entity {}
 
+#[derive(Debug)]
 pub enum Value {
-    V(V), 
+    V(V),
     Vec(Vec<Value>),
     Map(HashMap<Key, Value>),
 }
 
-pub struct HistorySnapshot {}
+// Values pulled through unbounded recursion can be nested arbitrarily deep, so
+// they are copied, compared and (through `dispose`) torn down iteratively
+// rather than by recursing once per level.
+impl Value {
+    // Drops the value one level at a time. The default drop recurses, and
+    // overflows the stack on deeply nested values.
+    pub fn dispose(self) {
+        let mut values = vec![self];
+        while let Some(value) = values.pop() {
+            match value {
+                Value::V(_) => {}
+                Value::Vec(children) => values.extend(children),
+                Value::Map(map) => values.extend(map.into_values()),
+            }
+        }
+    }
+}
+
+impl Clone for Value {
+    // Copies the value bottom up: each value visited pushes its copy onto
+    // `copied` once its children have been copied.
+    fn clone(&self) -> Value {
+        enum Task<'a> {
+            Visit(&'a Value),
+            Vec(usize),
+            Map(Vec<&'a Key>),
+        }
+        let mut tasks = vec![Task::Visit(self)];
+        let mut copied: Vec<Value> = vec![];
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Visit(Value::V(v)) => copied.push(Value::V(v.clone())),
+                Task::Visit(Value::Vec(values)) => {
+                    tasks.push(Task::Vec(values.len()));
+                    tasks.extend(values.iter().rev().map(Task::Visit));
+                }
+                Task::Visit(Value::Map(map)) => {
+                    let (keys, values): (Vec<&Key>, Vec<&Value>) = map.iter().unzip();
+                    tasks.push(Task::Map(keys));
+                    tasks.extend(values.into_iter().rev().map(Task::Visit));
+                }
+                Task::Vec(len) => {
+                    let values = copied.split_off(copied.len() - len);
+                    copied.push(Value::Vec(values));
+                }
+                Task::Map(keys) => {
+                    let values = copied.split_off(copied.len() - keys.len());
+                    copied.push(Value::Map(keys.into_iter().cloned().zip(values).collect()));
+                }
+            }
+        }
+        copied.pop().unwrap()
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        let mut pairs = vec![(self, other)];
+        while let Some(pair) = pairs.pop() {
+            match pair {
+                (Value::V(a), Value::V(b)) if a == b => {}
+                (Value::Vec(a), Value::Vec(b)) if a.len() == b.len() => {
+                    pairs.extend(a.iter().zip(b));
+                }
+                (Value::Map(a), Value::Map(b)) if a.len() == b.len() => {
+                    for (key, a) in a {
+                        match b.get(key) {
+                            Some(b) => pairs.push((a, b)),
+                            None => return false,
+                        }
+                    }
+                }
+                _ => return false,
+            }
+        }
+        true
+    }
+}
+
+// A view of every datom ever asserted or retracted, rather than only the
+// facts that currently hold. Each datom carries its `t` and `added` flag.
+pub struct HistorySnapshot {
+    db: DatabaseSnapshot,
+}
 
 #[allow(unused_variables)]
 impl DatabaseSnapshot {
     #[must_use]
     pub fn new() -> DatabaseSnapshot {
-        DatabaseSnapshot {
+        let db = DatabaseSnapshot {
             eavt: EAVTIndex::new(),
             aevt: AEVTIndex::new(),
-            // idents: Map::new(),
-            // attributes: Map::new(),
-        }
+            avet: AVETIndex::new(),
+            vaet: VAETIndex::new(),
+            basis_t: 0,
+            partitions: Map::new(),
+            as_of: None,
+            idents: Map::new(),
+            attributes: Map::new(),
+            functions: Map::new(),
+        };
+        let bootstrap = schema::bootstrap();
+        let db = bootstrap
+            .iter()
+            .cloned()
+            .fold(db, |db, datom| db.insert(datom));
+        db.refresh_schema(&bootstrap)
     }
 
+    // Only datoms of attributes that are indexed or unique go into AVET.
     #[must_use]
     pub fn insert(self, datom: Datom) -> Self {
+        let avet = match self.attributes.get(&datom.a) {
+            Some(attribute) if attribute.indexed() => self.avet.insert(datom.clone()),
+            _ => self.avet,
+        };
+        let (part, index) = (partition::partition(datom.e), partition::index(datom.e));
+        let partitions = match self.partitions.get(&part) {
+            _ if datom.e < 0 => self.partitions,
+            Some(next) if *next > index => self.partitions,
+            _ => self.partitions.insert(part, index + 1).0,
+        };
         DatabaseSnapshot {
+            basis_t: self.basis_t.max(datom.t),
+            partitions,
             eavt: self.eavt.insert(datom.clone()),
-            aevt: self.aevt.insert(datom),
-            // idents: self.idents,
-            // attributes: self.attributes,
+            aevt: self.aevt.insert(datom.clone()),
+            avet,
+            vaet: self.vaet.insert(datom),
+            ..self
         }
     }
 
+    // Brings the cached idents and attributes up to date with `datoms`, which
+    // have already been inserted. An attribute that becomes indexed has its
+    // existing datoms backfilled into AVET.
+    #[must_use]
+    pub(crate) fn refresh_schema(self, datoms: &[Datom]) -> Self {
+        let mut db = self;
+        for datom in datoms.iter().filter(|datom| datom.a == schema::DB_IDENT) {
+            if let V::Key(ident) = &datom.v {
+                db.idents = if datom.added {
+                    db.idents.insert(ident.clone(), datom.e).0
+                } else if db.idents.get(ident) == Some(&datom.e) {
+                    db.idents.remove(ident).0
+                } else {
+                    db.idents
+                };
+            }
+        }
+
+        let mut touched: Vec<EntityId> = datoms
+            .iter()
+            .filter(|datom| schema::is_schema_attribute(datom.a))
+            .map(|datom| datom.e)
+            .collect();
+        touched.sort_unstable();
+        touched.dedup();
+        for e in touched {
+            let was_indexed = db.attributes.get(&e).is_some_and(Attribute::indexed);
+            match Attribute::from_datoms(e, db.select_e(e)) {
+                Some(attribute) => {
+                    let indexed = attribute.indexed();
+                    db.attributes = db.attributes.insert(e, attribute).0;
+                    if indexed && !was_indexed {
+                        let history: Vec<Datom> = db.aevt.select_a(e).cloned().collect();
+                        db.avet = history
+                            .into_iter()
+                            .fold(db.avet, |avet, datom| avet.insert(datom));
+                    }
+                }
+                None => db.attributes = db.attributes.remove(&e).0,
+            }
+        }
+        db
+    }
+
+    // Resolves an identity given to one of the `select_*` methods, which yield
+    // nothing for identities that don't resolve.
+    fn resolve(&self, id: impl Into<Identity>) -> Option<EntityId> {
+        self.ent_id(id).ok()
+    }
+
+    // Whether a datom falls within this snapshot's as-of horizon.
+    fn visible(&self) -> impl Fn(&&Datom) -> bool {
+        let as_of = self.as_of;
+        move |datom| as_of.is_none_or(|t| datom.t <= t)
+    }
+
+    // Applies `tx_data` as a single transaction with a newly allocated
+    // `TransactionId`. The receiver is left untouched; the new state is
+    // returned as `db_after` on the report.
+    pub fn transact(&self, tx_data: Vec<TxOp>) -> Result<TxReport> {
+        transaction::transact(self, tx_data)
+    }
+
+    // Installs `f` as the transaction function named `ident`, which
+    // `TxOp::Call` expands into the tx ops `f` returns. The function's entity
+    // is created in `:db.part/db` (or, if `ident` already names one, replaced
+    // from then on) by a transaction of its own. An `ident` already naming an
+    // entity other than a function is refused.
+    pub fn install_fn(
+        &self,
+        ident: Key,
+        f: impl Fn(&DatabaseSnapshot, &[V]) -> Result<Vec<TxOp>> + Send + Sync + 'static,
+    ) -> Result<TxReport> {
+        if let Ok(e) = self.ent_id(ident.clone()) {
+            if self.select_eav(e, schema::DB_FN, &V::Boolean(true)).next().is_none() {
+                return Err(Error::IdentInUse(ident));
+            }
+        }
+        let fn_ref = || EntityRef::TempIdIn(partition::PART_DB, "fn".to_string());
+        let mut report = self.transact(vec![
+            TxOp::add(fn_ref(), schema::DB_IDENT, V::Key(ident)),
+            TxOp::add(fn_ref(), schema::DB_FN, V::Boolean(true)),
+        ])?;
+        let key = (report.tempids["fn"], report.db_after.basis_t());
+        let f: TxFn = Arc::new(f);
+        report.db_after.functions = report.db_after.functions.insert(key, f).0;
+        Ok(report)
+    }
+
+    // The transaction function installed on entity `e` as of the snapshot's
+    // horizon, provided `e` is still a function then.
+    pub(crate) fn function(&self, e: EntityId) -> Option<&TxFn> {
+        self.select_eav(e, schema::DB_FN, &V::Boolean(true)).next()?;
+        let t = self.as_of.unwrap_or(self.basis_t);
+        let mut installed = self.functions.range(Bound::Included((e, 0)), Bound::Included((e, t)));
+        installed.next_back().map(|(_, f)| f)
+    }
+
     // #region Datoms API 
+    // These only yield the facts that currently hold (as of the snapshot's
+    // horizon); see `history` for the raw stream of assertions and retractions.
     pub fn scan_eavt(&self) -> impl Iterator<Item = &Datom> {
-        self.eavt.scan()
+        current(self.eavt.scan().filter(self.visible()))
     }
-    pub fn select_e(&self, e: EntityId) -> impl Iterator<Item = &Datom> {
-        self.eavt.select_e(e)
+    pub fn select_e(&self, e: impl Into<Identity>) -> impl Iterator<Item = &Datom> {
+        let ids = self.resolve(e);
+        ids.into_iter().flat_map(move |e| current(self.eavt.select_e(e).filter(self.visible())))
     }
-    pub fn select_ea(&self, e: EntityId, a: AttributeId) -> impl Iterator<Item = &Datom> {
-        self.eavt.select_ea(e, a)
+    pub fn select_ea(
+        &self,
+        e: impl Into<Identity>,
+        a: impl Into<Identity>,
+    ) -> impl Iterator<Item = &Datom> {
+        let ids = self.resolve(e).zip(self.resolve(a));
+        ids.into_iter().flat_map(move |(e, a)| {
+            current(self.eavt.select_ea(e, a).filter(self.visible()))
+        })
     }
     pub fn select_eav(
         &self,
-        e: EntityId,
-        a: AttributeId,
+        e: impl Into<Identity>,
+        a: impl Into<Identity>,
         v: &V,
     ) -> impl Iterator<Item = &Datom> {
-        self.eavt.select_eav(e, a, v)
+        let v = v.clone();
+        let ids = self.resolve(e).zip(self.resolve(a));
+        ids.into_iter().flat_map(move |(e, a)| {
+            current(self.eavt.select_eav(e, a, &v).filter(self.visible()))
+        })
     }
     pub fn select_eavt(
         &self,
-        e: EntityId,
-        a: AttributeId,
+        e: impl Into<Identity>,
+        a: impl Into<Identity>,
         v: &V,
         t: TransactionId,
     ) -> impl Iterator<Item = &Datom> {
-        self.eavt.select_eavt(e, a, v, t)
+        let v = v.clone();
+        let ids = self.resolve(e).zip(self.resolve(a));
+        ids.into_iter()
+            .flat_map(move |(e, a)| {
+                current(self.eavt.select_eav(e, a, &v).filter(self.visible()))
+            })
+            .filter(move |datom| datom.t == t)
     }
 
     pub fn scan_aevt(&self) -> impl Iterator<Item = &Datom> {
-        self.aevt.scan()
+        current(self.aevt.scan().filter(self.visible()))
     }
-    pub fn select_a(&self, a: AttributeId) -> impl Iterator<Item = &Datom> {
-        self.aevt.select_a(a)
+    pub fn select_a(&self, a: impl Into<Identity>) -> impl Iterator<Item = &Datom> {
+        let ids = self.resolve(a);
+        ids.into_iter().flat_map(move |a| current(self.aevt.select_a(a).filter(self.visible())))
     }
-    pub fn select_ae(&self, a: AttributeId, e: EntityId) -> impl Iterator<Item = &Datom> {
-        self.aevt.select_ae(a, e)
+    pub fn select_ae(
+        &self,
+        a: impl Into<Identity>,
+        e: impl Into<Identity>,
+    ) -> impl Iterator<Item = &Datom> {
+        let ids = self.resolve(a).zip(self.resolve(e));
+        ids.into_iter().flat_map(move |(a, e)| {
+            current(self.aevt.select_ae(a, e).filter(self.visible()))
+        })
     }
     pub fn select_aev(
         &self,
-        a: AttributeId,
-        e: EntityId,
+        a: impl Into<Identity>,
+        e: impl Into<Identity>,
         v: &V,
     ) -> impl Iterator<Item = &Datom> {
-        self.aevt.select_aev(a, e, v)
+        let v = v.clone();
+        let ids = self.resolve(a).zip(self.resolve(e));
+        ids.into_iter().flat_map(move |(a, e)| {
+            current(self.aevt.select_aev(a, e, &v).filter(self.visible()))
+        })
     }
     pub fn select_aevt(
         &self,
-        a: AttributeId,
-        e: EntityId,
+        a: impl Into<Identity>,
+        e: impl Into<Identity>,
         v: &V,
         t: TransactionId,
     ) -> impl Iterator<Item = &Datom> {
-        self.aevt.select_aevt(a, e, v, t)
+        let v = v.clone();
+        let ids = self.resolve(a).zip(self.resolve(e));
+        ids.into_iter()
+            .flat_map(move |(a, e)| {
+                current(self.aevt.select_aev(a, e, &v).filter(self.visible()))
+            })
+            .filter(move |datom| datom.t == t)
+    }
+
+    pub fn scan_avet(&self) -> impl Iterator<Item = &Datom> {
+        current(self.avet.scan().filter(self.visible()))
+    }
+    pub fn select_av(&self, a: impl Into<Identity>, v: &V) -> impl Iterator<Item = &Datom> {
+        let v = v.clone();
+        let ids = self.resolve(a);
+        ids.into_iter().flat_map(move |a| {
+            current(self.avet.select_av(a, &v).filter(self.visible()))
+        })
+    }
+    pub fn select_av_range(
+        &self,
+        a: impl Into<Identity>,
+        start: Bound<&V>,
+        end: Bound<&V>,
+    ) -> impl Iterator<Item = &Datom> {
+        let (start, end) = (start.cloned(), end.cloned());
+        let ids = self.resolve(a);
+        ids.into_iter().flat_map(move |a| {
+            current(
+                self.avet.select_av_range(a, start.as_ref(), end.as_ref())
+                    .filter(self.visible()),
+            )
+        })
+    }
+    pub fn select_ave(
+        &self,
+        a: impl Into<Identity>,
+        v: &V,
+        e: impl Into<Identity>,
+    ) -> impl Iterator<Item = &Datom> {
+        let v = v.clone();
+        let ids = self.resolve(a).zip(self.resolve(e));
+        ids.into_iter().flat_map(move |(a, e)| {
+            current(self.avet.select_ave(a, &v, e).filter(self.visible()))
+        })
+    }
+    pub fn select_avet(
+        &self,
+        a: impl Into<Identity>,
+        v: &V,
+        e: impl Into<Identity>,
+        t: TransactionId,
+    ) -> impl Iterator<Item = &Datom> {
+        let v = v.clone();
+        let ids = self.resolve(a).zip(self.resolve(e));
+        ids.into_iter()
+            .flat_map(move |(a, e)| {
+                current(self.avet.select_ave(a, &v, e).filter(self.visible()))
+            })
+            .filter(move |datom| datom.t == t)
+    }
+
+    // Only datoms whose value is a `V::EntityId` are present in VAET.
+    pub fn scan_vaet(&self) -> impl Iterator<Item = &Datom> {
+        current(self.vaet.scan().filter(self.visible()))
+    }
+    pub fn select_v(&self, v: impl Into<Identity>) -> impl Iterator<Item = &Datom> {
+        let ids = self.resolve(v);
+        ids.into_iter().flat_map(move |v| current(self.vaet.select_v(v).filter(self.visible())))
+    }
+    pub fn select_va(
+        &self,
+        v: impl Into<Identity>,
+        a: impl Into<Identity>,
+    ) -> impl Iterator<Item = &Datom> {
+        let ids = self.resolve(v).zip(self.resolve(a));
+        ids.into_iter().flat_map(move |(v, a)| {
+            current(self.vaet.select_va(v, a).filter(self.visible()))
+        })
+    }
+    pub fn select_vae(
+        &self,
+        v: impl Into<Identity>,
+        a: impl Into<Identity>,
+        e: impl Into<Identity>,
+    ) -> impl Iterator<Item = &Datom> {
+        let ids = self
+            .resolve(v)
+            .zip(self.resolve(a))
+            .zip(self.resolve(e));
+        ids.into_iter().flat_map(move |((v, a), e)| {
+            current(self.vaet.select_vae(v, a, e).filter(self.visible()))
+        })
+    }
+    pub fn select_vaet(
+        &self,
+        v: impl Into<Identity>,
+        a: impl Into<Identity>,
+        e: impl Into<Identity>,
+        t: TransactionId,
+    ) -> impl Iterator<Item = &Datom> {
+        let ids = self
+            .resolve(v)
+            .zip(self.resolve(a))
+            .zip(self.resolve(e));
+        ids.into_iter()
+            .flat_map(move |((v, a), e)| {
+                current(self.vaet.select_vae(v, a, e).filter(self.visible()))
+            })
+            .filter(move |datom| datom.t == t)
     }
     // endregion
 
+    // Returns a view of the database as it was at `t`. An instant resolves,
+    // through the `:db/txInstant` of each transaction, to the last transaction
+    // applied at or before it. Transaction instants never decrease, so that is
+    // the transaction with the last instant in AVET up to `t`.
     pub fn as_of(&self, t: &Time) -> Self {
-        unimplemented!()
+        // A view is only ever narrowed: an as-of view can't see past its own
+        // horizon however late the time it is asked for.
+        let as_of = match t {
+            Time::TransactionId(t) => self.as_of.map_or(*t, |as_of| as_of.min(*t)),
+            Time::Instant(instant) => {
+                let instant = V::Instant(*instant);
+                let end = Bound::Included(&instant);
+                self.avet
+                    .select_av_range(schema::DB_TX_INSTANT, Bound::Unbounded, end)
+                    .rev()
+                    .filter(self.visible())
+                    .find(|datom| partition::partition(datom.e) == partition::PART_TX)
+                    .map_or(0, |datom| datom.t)
+            }
+        };
+        let db = DatabaseSnapshot {
+            as_of: Some(as_of),
+            ..self.clone()
+        };
+        match self.as_of {
+            None if as_of >= self.basis_t => db,
+            _ => db.visible_schema(),
+        }
     }
-    pub fn as_of_t(&self) -> &Time {
-        unimplemented!()
+    // Rebuilds the cached idents and attributes from the datoms visible in the
+    // snapshot, so that an as-of view resolves them as they were at the time.
+    fn visible_schema(self) -> Self {
+        let idents = self.select_a(schema::DB_IDENT).filter_map(|datom| match &datom.v {
+            V::Key(ident) => Some((ident.clone(), datom.e)),
+            _ => None,
+        });
+        let idents = idents.fold(Map::new(), |idents, (ident, e)| idents.insert(ident, e).0);
+        let attributes = self
+            .select_a(schema::DB_VALUE_TYPE)
+            .filter_map(|datom| Attribute::from_datoms(datom.e, self.select_e(datom.e)))
+            .fold(Map::new(), |attributes, attribute| attributes.insert(attribute.id, attribute).0);
+        DatabaseSnapshot { idents, attributes, ..self }
     }
-    pub fn attribute(&self, attr_id: &Identity) -> &Attribute {
-        unimplemented!()
+    pub fn as_of_t(&self) -> Option<TransactionId> {
+        self.as_of
     }
-    pub fn basis_t(&self) -> &Time {
-        unimplemented!()
+    pub fn attribute(&self, attr_id: impl Into<Identity>) -> Option<&Attribute> {
+        let id = self.ent_id(attr_id).ok()?;
+        self.attributes.get(&id)
     }
-    pub fn ent_id(&self, ident: Identity) -> EntityId {
-        unimplemented!()
+    pub fn basis_t(&self) -> TransactionId {
+        self.basis_t
+    }
+    // The id the next entity allocated in `partition` will receive.
+    pub(crate) fn next_entity_id(&self, partition: EntityId) -> EntityId {
+        let index = self.partitions.get(&partition).copied().unwrap_or(0);
+        partition::entity_id(partition, index)
+    }
+    // Resolves keywords through `:db/ident` and lookup refs through the AVET
+    // index of their (unique) attribute. Entity ids are returned as they are.
+    pub fn ent_id(&self, ident: impl Into<Identity>) -> Result<EntityId> {
+        let ident = ident.into();
+        match &ident {
+            Identity::EntityId(e) => Ok(*e),
+            Identity::Keyword(key) => self.idents.get(key).copied().ok_or(Error::NotFound(ident)),
+            Identity::LookupRef(LookupRef { attr, value }) => {
+                let attribute = self
+                    .attribute(attr.clone())
+                    .ok_or_else(|| Error::NotFound(Identity::Keyword(attr.clone())))?;
+                if attribute.unique.is_none() {
+                    return Err(Error::NotUnique(attr.clone()));
+                }
+                let e = self.select_av(attribute.id, value).next().map(|datom| datom.e);
+                e.ok_or(Error::NotFound(ident))
+            }
+        }
     }
     pub fn entity(&self, eid: EntityId) -> Entity {
         unimplemented!()
@@ -149,15 +609,236 @@ impl DatabaseSnapshot {
         unimplemented!()
     }
     pub fn history(&self) -> HistorySnapshot {
-        unimplemented!()
+        HistorySnapshot { db: self.clone() }
     }
-    pub fn ident(&self, eid: EntityId) -> Identity {
-        unimplemented!()
+    // The `:db/ident` of the identified entity.
+    pub fn ident(&self, id: impl Into<Identity>) -> Result<Key> {
+        let id = id.into();
+        let e = self.ent_id(id.clone())?;
+        let ident = self.select_ea(e, schema::DB_IDENT).next().map(|datom| &datom.v);
+        match ident {
+            Some(V::Key(key)) => Ok(key.clone()),
+            _ => Err(Error::NotFound(id)),
+        }
     }
-    pub fn pull(&self, p: &Pattern, eid: EntityId) -> Value {
-        unimplemented!()
+    // The attributes of `eid` selected by `p`, as a `Value::Map`.
+    pub fn pull(&self, p: &Pattern, eid: impl Into<Identity>) -> Result<Value> {
+        Ok(pull::pull(self, p, self.ent_id(eid)?))
+    }
+    // Pulls `p` for each of `eids`, in the same order. Cheaper than calling
+    // `pull` for each, as the pattern is only resolved against the schema once.
+    pub fn pull_many<I: Into<Identity>>(
+        &self,
+        p: &Pattern,
+        eids: impl IntoIterator<Item = I>,
+    ) -> Result<Vec<Value>> {
+        let eids = eids.into_iter().map(|eid| self.ent_id(eid)).collect::<Result<Vec<_>>>()?;
+        Ok(pull::pull_many(self, p, &eids))
+    }
+    // Runs `q` against this snapshot, with `args` for the inputs after `$`.
+    pub fn query(&self, q: &Query, args: Vec<Arg>) -> Result<QueryResult> {
+        query::query(self, q, args)
+    }
+}
+
+impl HistorySnapshot {
+    pub fn basis_t(&self) -> TransactionId {
+        self.db.basis_t()
+    }
+    pub fn as_of_t(&self) -> Option<TransactionId> {
+        self.db.as_of_t()
+    }
+    // The snapshot this is the history of.
+    pub(crate) fn db(&self) -> &DatabaseSnapshot {
+        &self.db
+    }
+    // Runs `q` against every assertion and retraction up to the snapshot's
+    // horizon. Clauses can bind a datom's transaction and whether it was added.
+    pub fn query(&self, q: &Query, args: Vec<Arg>) -> Result<QueryResult> {
+        query::query(self, q, args)
+    }
+
+    // #region Datoms API 
+    pub fn scan_eavt(&self) -> impl Iterator<Item = &Datom> {
+        self.db.eavt.scan().filter(self.db.visible())
+    }
+    pub fn select_e(&self, e: impl Into<Identity>) -> impl Iterator<Item = &Datom> {
+        let ids = self.db.resolve(e);
+        ids.into_iter().flat_map(move |e| self.db.eavt.select_e(e).filter(self.db.visible()))
+    }
+    pub fn select_ea(
+        &self,
+        e: impl Into<Identity>,
+        a: impl Into<Identity>,
+    ) -> impl Iterator<Item = &Datom> {
+        let ids = self.db.resolve(e).zip(self.db.resolve(a));
+        ids.into_iter().flat_map(move |(e, a)| {
+            self.db.eavt.select_ea(e, a).filter(self.db.visible())
+        })
+    }
+    pub fn select_eav(
+        &self,
+        e: impl Into<Identity>,
+        a: impl Into<Identity>,
+        v: &V,
+    ) -> impl Iterator<Item = &Datom> {
+        let v = v.clone();
+        let ids = self.db.resolve(e).zip(self.db.resolve(a));
+        ids.into_iter().flat_map(move |(e, a)| {
+            self.db.eavt.select_eav(e, a, &v).filter(self.db.visible())
+        })
+    }
+    pub fn select_eavt(
+        &self,
+        e: impl Into<Identity>,
+        a: impl Into<Identity>,
+        v: &V,
+        t: TransactionId,
+    ) -> impl Iterator<Item = &Datom> {
+        let v = v.clone();
+        let ids = self.db.resolve(e).zip(self.db.resolve(a));
+        ids.into_iter().flat_map(move |(e, a)| {
+            self.db.eavt.select_eavt(e, a, &v, t).filter(self.db.visible())
+        })
+    }
+
+    pub fn scan_aevt(&self) -> impl Iterator<Item = &Datom> {
+        self.db.aevt.scan().filter(self.db.visible())
+    }
+    pub fn select_a(&self, a: impl Into<Identity>) -> impl Iterator<Item = &Datom> {
+        let ids = self.db.resolve(a);
+        ids.into_iter().flat_map(move |a| self.db.aevt.select_a(a).filter(self.db.visible()))
+    }
+    pub fn select_ae(
+        &self,
+        a: impl Into<Identity>,
+        e: impl Into<Identity>,
+    ) -> impl Iterator<Item = &Datom> {
+        let ids = self.db.resolve(a).zip(self.db.resolve(e));
+        ids.into_iter().flat_map(move |(a, e)| {
+            self.db.aevt.select_ae(a, e).filter(self.db.visible())
+        })
+    }
+    pub fn select_aev(
+        &self,
+        a: impl Into<Identity>,
+        e: impl Into<Identity>,
+        v: &V,
+    ) -> impl Iterator<Item = &Datom> {
+        let v = v.clone();
+        let ids = self.db.resolve(a).zip(self.db.resolve(e));
+        ids.into_iter().flat_map(move |(a, e)| {
+            self.db.aevt.select_aev(a, e, &v).filter(self.db.visible())
+        })
+    }
+    pub fn select_aevt(
+        &self,
+        a: impl Into<Identity>,
+        e: impl Into<Identity>,
+        v: &V,
+        t: TransactionId,
+    ) -> impl Iterator<Item = &Datom> {
+        let v = v.clone();
+        let ids = self.db.resolve(a).zip(self.db.resolve(e));
+        ids.into_iter().flat_map(move |(a, e)| {
+            self.db.aevt.select_aevt(a, e, &v, t).filter(self.db.visible())
+        })
     }
 
+    pub fn scan_avet(&self) -> impl Iterator<Item = &Datom> {
+        self.db.avet.scan().filter(self.db.visible())
+    }
+    pub fn select_av(&self, a: impl Into<Identity>, v: &V) -> impl Iterator<Item = &Datom> {
+        let v = v.clone();
+        let ids = self.db.resolve(a);
+        ids.into_iter().flat_map(move |a| self.db.avet.select_av(a, &v).filter(self.db.visible()))
+    }
+    pub fn select_av_range(
+        &self,
+        a: impl Into<Identity>,
+        start: Bound<&V>,
+        end: Bound<&V>,
+    ) -> impl Iterator<Item = &Datom> {
+        let (start, end) = (start.cloned(), end.cloned());
+        let ids = self.db.resolve(a);
+        ids.into_iter().flat_map(move |a| {
+            self.db.avet.select_av_range(a, start.as_ref(), end.as_ref()).filter(self.db.visible())
+        })
+    }
+    pub fn select_ave(
+        &self,
+        a: impl Into<Identity>,
+        v: &V,
+        e: impl Into<Identity>,
+    ) -> impl Iterator<Item = &Datom> {
+        let v = v.clone();
+        let ids = self.db.resolve(a).zip(self.db.resolve(e));
+        ids.into_iter().flat_map(move |(a, e)| {
+            self.db.avet.select_ave(a, &v, e).filter(self.db.visible())
+        })
+    }
+    pub fn select_avet(
+        &self,
+        a: impl Into<Identity>,
+        v: &V,
+        e: impl Into<Identity>,
+        t: TransactionId,
+    ) -> impl Iterator<Item = &Datom> {
+        let v = v.clone();
+        let ids = self.db.resolve(a).zip(self.db.resolve(e));
+        ids.into_iter().flat_map(move |(a, e)| {
+            self.db.avet.select_avet(a, &v, e, t).filter(self.db.visible())
+        })
+    }
+
+    // Only datoms whose value is a `V::EntityId` are present in VAET.
+    pub fn scan_vaet(&self) -> impl Iterator<Item = &Datom> {
+        self.db.vaet.scan().filter(self.db.visible())
+    }
+    pub fn select_v(&self, v: impl Into<Identity>) -> impl Iterator<Item = &Datom> {
+        let ids = self.db.resolve(v);
+        ids.into_iter().flat_map(move |v| self.db.vaet.select_v(v).filter(self.db.visible()))
+    }
+    pub fn select_va(
+        &self,
+        v: impl Into<Identity>,
+        a: impl Into<Identity>,
+    ) -> impl Iterator<Item = &Datom> {
+        let ids = self.db.resolve(v).zip(self.db.resolve(a));
+        ids.into_iter().flat_map(move |(v, a)| {
+            self.db.vaet.select_va(v, a).filter(self.db.visible())
+        })
+    }
+    pub fn select_vae(
+        &self,
+        v: impl Into<Identity>,
+        a: impl Into<Identity>,
+        e: impl Into<Identity>,
+    ) -> impl Iterator<Item = &Datom> {
+        let ids = self.db
+            .resolve(v)
+            .zip(self.db.resolve(a))
+            .zip(self.db.resolve(e));
+        ids.into_iter().flat_map(move |((v, a), e)| {
+            self.db.vaet.select_vae(v, a, e).filter(self.db.visible())
+        })
+    }
+    pub fn select_vaet(
+        &self,
+        v: impl Into<Identity>,
+        a: impl Into<Identity>,
+        e: impl Into<Identity>,
+        t: TransactionId,
+    ) -> impl Iterator<Item = &Datom> {
+        let ids = self.db
+            .resolve(v)
+            .zip(self.db.resolve(a))
+            .zip(self.db.resolve(e));
+        ids.into_iter().flat_map(move |((v, a), e)| {
+            self.db.vaet.select_vaet(v, a, e, t).filter(self.db.visible())
+        })
+    }
+    // endregion
 }
 
 impl Default for DatabaseSnapshot {
@@ -165,3 +846,201 @@ impl Default for DatabaseSnapshot {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::time::SystemTime;
+
+    use crate::{
+        database_snapshot::{DatabaseSnapshot, Identity, LookupRef, Time},
+        error::Error,
+        schema::{
+            test::{db, E, ID, LONGS, STRING},
+            DB_CARDINALITY, DB_IDENT, DB_VALUE_TYPE,
+        },
+        transaction::TxOp,
+        Key, V,
+    };
+
+    #[test]
+    fn as_of() {
+        let db = db();
+        let db = db.transact(vec![TxOp::add(E, LONGS, V::I64(1))]).unwrap().db_after;
+        let between = SystemTime::now();
+        let db = db
+            .transact(vec![TxOp::add(E, STRING, V::String("a".to_string()))])
+            .unwrap()
+            .db_after;
+        let db = db.transact(vec![TxOp::retract(E, LONGS, V::I64(1))]).unwrap().db_after;
+
+        let holds = |db: &DatabaseSnapshot, a| db.select_ea(E, a).next().is_some();
+
+        let db2 = db.as_of(&Time::TransactionId(2));
+        assert_eq!(db2.as_of_t(), Some(2));
+        assert_eq!(db2.basis_t(), 4);
+        assert!(holds(&db2, LONGS));
+        assert!(!holds(&db2, STRING));
+        assert_eq!(db2.select_e(E).count(), 1);
+
+        let db3 = db.as_of(&Time::TransactionId(3));
+        assert!(holds(&db3, LONGS));
+        assert!(holds(&db3, STRING));
+        assert!(!holds(&db, LONGS));
+
+        let db_instant = db.as_of(&Time::Instant(between));
+        assert_eq!(db_instant.as_of_t(), Some(2));
+        assert!(db_instant.transact(vec![]).is_err());
+    }
+
+    #[test]
+    fn as_of_resolves_the_schema_of_the_time() {
+        let other = || Key::from(":test/other");
+        let db = db();
+        let before = db.basis_t();
+        let db = db
+            .transact(vec![
+                TxOp::add("other", DB_IDENT, V::Key(other())),
+                TxOp::add("other", DB_VALUE_TYPE, V::Key(Key::from(":db.type/long"))),
+                TxOp::add("other", DB_CARDINALITY, V::Key(Key::from(":db.cardinality/one"))),
+            ])
+            .unwrap()
+            .db_after;
+        let installed = db.basis_t();
+        let e = db.ent_id(other()).unwrap();
+        let renamed = Key::from(":test/renamed");
+        let db = db
+            .transact(vec![TxOp::add(e, DB_IDENT, V::Key(renamed.clone()))])
+            .unwrap()
+            .db_after;
+        assert_eq!(db.ent_id(renamed.clone()), Ok(e));
+        assert!(db.ent_id(other()).is_err());
+
+        let then = db.as_of(&Time::TransactionId(installed));
+        assert_eq!(then.ent_id(other()), Ok(e));
+        assert!(then.ent_id(renamed.clone()).is_err());
+        assert_eq!(then.attribute(e).map(|attribute| attribute.ident.clone()), Some(other()));
+
+        let earlier = db.as_of(&Time::TransactionId(before));
+        assert!(earlier.attribute(e).is_none());
+        assert!(earlier.ent_id(other()).is_err());
+        assert_eq!(earlier.attribute(LONGS).map(|attribute| attribute.id), Some(LONGS));
+    }
+
+    #[test]
+    fn as_of_never_widens_a_view() {
+        let db = db();
+        let before = db.basis_t();
+        let db = db.transact(vec![TxOp::add(E, LONGS, V::I64(1))]).unwrap().db_after;
+        let then = db.as_of(&Time::TransactionId(before));
+        let later = then.as_of(&Time::TransactionId(db.basis_t()));
+        assert_eq!(later.as_of_t(), Some(before));
+        assert_eq!(later.select_ea(E, LONGS).count(), 0);
+        let earlier = then.as_of(&Time::TransactionId(before - 1));
+        assert_eq!(earlier.as_of_t(), Some(before - 1));
+    }
+
+    #[test]
+    fn current_state() {
+        let db = db();
+        let db = db
+            .transact(vec![TxOp::add(E, LONGS, V::I64(1)), TxOp::add(E, LONGS, V::I64(2))])
+            .unwrap()
+            .db_after;
+        let db = db.transact(vec![TxOp::retract(E, LONGS, V::I64(1))]).unwrap().db_after;
+        let db = db
+            .transact(vec![TxOp::retract(E, LONGS, V::I64(2)), TxOp::add(E, LONGS, V::I64(3))])
+            .unwrap()
+            .db_after;
+        let db = db.transact(vec![TxOp::add(E, LONGS, V::I64(1))]).unwrap().db_after;
+
+        let current: Vec<_> = db
+            .select_ea(E, LONGS)
+            .map(|datom| (datom.v.clone(), datom.t))
+            .collect();
+        assert_eq!(current, vec![(V::I64(1), 5), (V::I64(3), 4)]);
+        assert_eq!(db.select_av(LONGS, &V::I64(2)).count(), 0);
+        assert_eq!(db.select_av(LONGS, &V::I64(3)).count(), 1);
+        assert_eq!(db.select_aevt(LONGS, E, &V::I64(1), 2).count(), 0);
+        assert_eq!(db.select_aevt(LONGS, E, &V::I64(1), 5).count(), 1);
+        assert_eq!(db.history().select_e(E).count(), 6);
+    }
+
+    #[test]
+    fn history() {
+        let db = db();
+        let db = db.transact(vec![TxOp::add(E, LONGS, V::I64(1))]).unwrap().db_after;
+        let db = db.transact(vec![TxOp::retract(E, LONGS, V::I64(1))]).unwrap().db_after;
+        let db = db.transact(vec![TxOp::add(E, LONGS, V::I64(1))]).unwrap().db_after;
+
+        let history: Vec<_> = db
+            .history()
+            .select_ea(E, LONGS)
+            .map(|datom| (datom.t, datom.added))
+            .collect();
+        assert_eq!(history, vec![(2, true), (3, false), (4, true)]);
+
+        let history = db.as_of(&Time::TransactionId(3)).history();
+        assert_eq!(history.select_av(LONGS, &V::I64(1)).count(), 2);
+    }
+
+    #[test]
+    fn identity_resolution() {
+        let db = db()
+            .transact(vec![
+                TxOp::add(E, ID, V::String("ada".to_string())),
+                TxOp::add(E, STRING, V::String("Ada".to_string())),
+            ])
+            .unwrap()
+            .db_after;
+
+        let by_id = LookupRef::new(Key::from(":test/id"), V::String("ada".to_string()));
+        assert_eq!(db.ent_id(Identity::LookupRef(by_id)), Ok(E));
+        assert_eq!(db.ent_id(Identity::Keyword(Key::from(":test/longs"))), Ok(LONGS));
+        assert_eq!(db.ent_id(Identity::EntityId(E)), Ok(E));
+
+        let missing = Identity::LookupRef(LookupRef::new(
+            Key::from(":test/id"),
+            V::String("grace".to_string()),
+        ));
+        assert_eq!(db.ent_id(missing.clone()), Err(Error::NotFound(missing)));
+        let by_name = LookupRef::new(Key::from(":test/string"), V::String("Ada".to_string()));
+        assert_eq!(
+            db.ent_id(Identity::LookupRef(by_name)),
+            Err(Error::NotUnique(Key::from(":test/string")))
+        );
+        let unknown = Identity::Keyword(Key::from(":test/unknown"));
+        assert_eq!(db.ent_id(unknown.clone()), Err(Error::NotFound(unknown)));
+
+        assert_eq!(db.ident(Identity::EntityId(LONGS)), Ok(Key::from(":test/longs")));
+        assert_eq!(
+            db.ident(Identity::Keyword(Key::from(":db.part/user"))),
+            Ok(Key::from(":db.part/user"))
+        );
+        assert_eq!(db.ident(Identity::EntityId(E)), Err(Error::NotFound(Identity::EntityId(E))));
+    }
+
+    #[test]
+    fn select_by_identity() {
+        let db = db()
+            .transact(vec![
+                TxOp::add(E, ID, V::String("ada".to_string())),
+                TxOp::add(E, LONGS, V::I64(1)),
+                TxOp::add(E, LONGS, V::I64(2)),
+            ])
+            .unwrap()
+            .db_after;
+
+        let ada = LookupRef::new(Key::from(":test/id"), V::String("ada".to_string()));
+        let longs = Key::from(":test/longs");
+        assert_eq!(db.select_ea(ada.clone(), longs.clone()).count(), 2);
+        assert_eq!(db.select_ae(longs.clone(), ada.clone()).count(), 2);
+        assert_eq!(db.select_eav(ada.clone(), LONGS, &V::I64(2)).count(), 1);
+        assert_eq!(db.select_av(longs.clone(), &V::I64(1)).next().map(|d| d.e), Some(E));
+        assert_eq!(db.select_e(ada).count(), 3);
+
+        let grace = LookupRef::new(Key::from(":test/id"), V::String("grace".to_string()));
+        assert_eq!(db.select_e(grace).count(), 0);
+        assert_eq!(db.history().select_a(Key::from(":test/unknown")).count(), 0);
+        assert_eq!(db.history().select_ea(E, longs).count(), 2);
+    }
+}
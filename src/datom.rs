@@ -8,6 +8,7 @@ pub struct Datom {
     pub a: AttributeId,
     pub v: V,
     pub t: TransactionId,
+    pub added: bool,
 }
 
 impl std::fmt::Debug for Datom {
@@ -20,14 +21,16 @@ impl std::fmt::Debug for Datom {
         ::std::fmt::Debug::fmt(&self.v, f)?;
         f.write_str(" ")?;
         ::std::fmt::Debug::fmt(&self.t, f)?;
+        f.write_str(" ")?;
+        ::std::fmt::Debug::fmt(&self.added, f)?;
         f.write_str("]")?;
         Ok(())
     }
 }
 
 impl Datom {
-    pub fn new(e: EntityId, a: AttributeId, v: V, t: TransactionId) -> Datom {
-        Datom { e, a, v, t }
+    pub fn new(e: EntityId, a: AttributeId, v: V, t: TransactionId, added: bool) -> Datom {
+        Datom { e, a, v, t, added }
     }
 }
 
@@ -45,7 +48,7 @@ impl DatomTrait for Datom {
         self.t
     }
     fn added(self) -> bool {
-        self.e >= 0
+        self.added
     }
 }
 
@@ -80,7 +83,12 @@ impl Ord for EAVTDatom {
             if ord == Ordering::Equal {
                 let ord = self.datom.v.cmp(&other.datom.v);
                 if ord == Ordering::Equal {
-                    self.datom.t.cmp(&other.datom.t)
+                    let ord = self.datom.t.cmp(&other.datom.t);
+                    if ord == Ordering::Equal {
+                        self.datom.added.cmp(&other.datom.added)
+                    } else {
+                        ord
+                    }
                 } else {
                     ord
                 }
@@ -124,7 +132,110 @@ impl Ord for AEVTDatom {
             if ord == Ordering::Equal {
                 let ord = self.datom.v.cmp(&other.datom.v);
                 if ord == Ordering::Equal {
-                    self.datom.t.cmp(&other.datom.t)
+                    let ord = self.datom.t.cmp(&other.datom.t);
+                    if ord == Ordering::Equal {
+                        self.datom.added.cmp(&other.datom.added)
+                    } else {
+                        ord
+                    }
+                } else {
+                    ord
+                }
+            } else {
+                ord
+            }
+        } else {
+            ord
+        }
+    }
+}
+
+#[derive(Shrinkwrap, Clone, PartialEq, Eq)]
+pub(crate) struct AVETDatom {
+    pub datom: Datom,
+}
+
+impl std::fmt::Debug for AVETDatom {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::result::Result<(), ::std::fmt::Error> {
+        ::std::fmt::Debug::fmt(&self.datom, f)
+    }
+}
+
+impl From<Datom> for AVETDatom {
+    fn from(datom: Datom) -> AVETDatom {
+        AVETDatom { datom }
+    }
+}
+
+impl PartialOrd for AVETDatom {
+    fn partial_cmp(&self, other: &AVETDatom) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for AVETDatom {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let ord = self.datom.a.cmp(&other.datom.a);
+        if ord == Ordering::Equal {
+            let ord = self.datom.v.cmp(&other.datom.v);
+            if ord == Ordering::Equal {
+                let ord = self.datom.e.cmp(&other.datom.e);
+                if ord == Ordering::Equal {
+                    let ord = self.datom.t.cmp(&other.datom.t);
+                    if ord == Ordering::Equal {
+                        self.datom.added.cmp(&other.datom.added)
+                    } else {
+                        ord
+                    }
+                } else {
+                    ord
+                }
+            } else {
+                ord
+            }
+        } else {
+            ord
+        }
+    }
+}
+
+#[derive(Shrinkwrap, Clone, PartialEq, Eq)]
+pub(crate) struct VAETDatom {
+    pub datom: Datom,
+}
+
+impl std::fmt::Debug for VAETDatom {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::result::Result<(), ::std::fmt::Error> {
+        ::std::fmt::Debug::fmt(&self.datom, f)
+    }
+}
+
+impl From<Datom> for VAETDatom {
+    fn from(datom: Datom) -> VAETDatom {
+        VAETDatom { datom }
+    }
+}
+
+impl PartialOrd for VAETDatom {
+    fn partial_cmp(&self, other: &VAETDatom) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for VAETDatom {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let ord = self.datom.v.cmp(&other.datom.v);
+        if ord == Ordering::Equal {
+            let ord = self.datom.a.cmp(&other.datom.a);
+            if ord == Ordering::Equal {
+                let ord = self.datom.e.cmp(&other.datom.e);
+                if ord == Ordering::Equal {
+                    let ord = self.datom.t.cmp(&other.datom.t);
+                    if ord == Ordering::Equal {
+                        self.datom.added.cmp(&other.datom.added)
+                    } else {
+                        ord
+                    }
                 } else {
                     ord
                 }
@@ -0,0 +1,363 @@
+use std::{iter::Peekable, str::Chars};
+
+use crate::{
+    error::{Error, Result},
+    Key, V,
+};
+
+// Where a form starts in the text it was read from. Lines and columns count
+// from 1, and columns count characters rather than bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+// A form read from EDN text, along with where it was read from so that the
+// code interpreting it can report errors against the text.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Form {
+    pub edn: Edn,
+    pub position: Position,
+}
+
+// The subset of EDN used by pull patterns and queries. Maps keep their entries
+// in the order they were written.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Edn {
+    Nil,
+    Boolean(bool),
+    Integer(i64),
+    String(String),
+    Keyword(Key),
+    Symbol(String),
+    Uuid(uuid::Uuid),
+    List(Vec<Form>),
+    Vector(Vec<Form>),
+    Map(Vec<(Form, Form)>),
+    Set(Vec<Form>),
+}
+
+impl Form {
+    // The error to report for this form.
+    pub fn error(&self, message: impl Into<String>) -> Error {
+        self.position.error(message)
+    }
+
+    // The scalar this form denotes, if it is one that can be held in a datom.
+    pub fn v(&self) -> Option<V> {
+        match &self.edn {
+            Edn::Boolean(b) => Some(V::Boolean(*b)),
+            Edn::Integer(i) => Some(V::I64(*i)),
+            Edn::String(s) => Some(V::String(s.clone())),
+            Edn::Keyword(key) => Some(V::Key(key.clone())),
+            Edn::Uuid(uuid) => Some(V::Uuid(*uuid)),
+            _ => None,
+        }
+    }
+}
+
+// Reads the single form in `input`.
+pub fn parse(input: &str) -> Result<Form> {
+    let mut reader = Reader::new(input);
+    let form = match reader.read()? {
+        Some(Read::Form(form)) => form,
+        Some(Read::Close(c, position)) => return Err(position.unexpected(c)),
+        None => return Err(reader.error("expected a form")),
+    };
+    match reader.read()? {
+        None => Ok(form),
+        Some(Read::Form(form)) => Err(form.error("unexpected form after the end of input")),
+        Some(Read::Close(c, position)) => Err(position.unexpected(c)),
+    }
+}
+
+impl Position {
+    fn error(self, message: impl Into<String>) -> Error {
+        Error::Parse {
+            message: message.into(),
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    fn unexpected(self, c: char) -> Error {
+        self.error(format!("unmatched delimiter {}", c))
+    }
+}
+
+enum Read {
+    Form(Form),
+    // A closing delimiter, which ends the collection being read, if any.
+    Close(char, Position),
+}
+
+// How deeply forms may nest, counting the forms that #_ discards. Forms are
+// read recursively, so without a limit deeply nested input would overflow the
+// stack.
+const MAX_DEPTH: usize = 128;
+
+struct Reader<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+    column: usize,
+    // The number of reads in progress.
+    depth: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(input: &'a str) -> Reader<'a> {
+        Reader {
+            chars: input.chars().peekable(),
+            line: 1,
+            column: 1,
+            depth: 0,
+        }
+    }
+
+    fn position(&self) -> Position {
+        Position {
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    fn error(&self, message: impl Into<String>) -> Error {
+        self.position().error(message)
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    // Skips whitespace (commas included) and comments.
+    fn skip(&mut self) {
+        while let Some(&c) = self.chars.peek() {
+            if c == ';' {
+                while self.next().is_some_and(|c| c != '\n') {}
+            } else if c.is_whitespace() || c == ',' {
+                self.next();
+            } else {
+                break;
+            }
+        }
+    }
+
+    // Reads the next form or closing delimiter, or None at the end of input.
+    fn read(&mut self) -> Result<Option<Read>> {
+        self.skip();
+        if self.depth == MAX_DEPTH {
+            return Err(self.error("forms nested too deeply"));
+        }
+        self.depth += 1;
+        let read = self.read_form();
+        self.depth -= 1;
+        read
+    }
+
+    fn read_form(&mut self) -> Result<Option<Read>> {
+        let position = self.position();
+        let c = match self.next() {
+            Some(c) => c,
+            None => return Ok(None),
+        };
+        let edn = match c {
+            ')' | ']' | '}' => return Ok(Some(Read::Close(c, position))),
+            '(' => Edn::List(self.read_until(')', position)?),
+            '[' => Edn::Vector(self.read_until(']', position)?),
+            '{' => {
+                let forms = self.read_until('}', position)?;
+                if forms.len() % 2 != 0 {
+                    return Err(position.error("map literal must contain an even number of forms"));
+                }
+                let mut forms = forms.into_iter();
+                let mut entries = Vec::new();
+                while let (Some(key), Some(value)) = (forms.next(), forms.next()) {
+                    entries.push((key, value));
+                }
+                Edn::Map(entries)
+            }
+            '"' => Edn::String(self.read_string(position)?),
+            '#' => return self.read_dispatch(position),
+            ':' => {
+                let name = self.read_token();
+                if name.is_empty() {
+                    return Err(position.error("expected a keyword name after :"));
+                }
+                Edn::Keyword(Key(format!(":{}", name)))
+            }
+            _ => {
+                let token = format!("{}{}", c, self.read_token());
+                self.read_atom(&token, position)?
+            }
+        };
+        Ok(Some(Read::Form(Form { edn, position })))
+    }
+
+    // Reads forms up to the `close` delimiter of a collection opened at `open`.
+    fn read_until(&mut self, close: char, open: Position) -> Result<Vec<Form>> {
+        let mut forms = Vec::new();
+        loop {
+            match self.read()? {
+                Some(Read::Form(form)) => forms.push(form),
+                Some(Read::Close(c, _)) if c == close => return Ok(forms),
+                Some(Read::Close(c, position)) => return Err(position.unexpected(c)),
+                None => {
+                    return Err(open.error(format!("unterminated collection, expected {}", close)))
+                }
+            }
+        }
+    }
+
+    fn read_string(&mut self, open: Position) -> Result<String> {
+        let mut s = String::new();
+        loop {
+            let position = self.position();
+            match self.next() {
+                Some('"') => return Ok(s),
+                Some('\\') => match self.next() {
+                    Some('"') => s.push('"'),
+                    Some('\\') => s.push('\\'),
+                    Some('n') => s.push('\n'),
+                    Some('t') => s.push('\t'),
+                    Some('r') => s.push('\r'),
+                    Some(c) => return Err(position.error(format!("unsupported escape \\{}", c))),
+                    None => return Err(open.error("unterminated string")),
+                },
+                Some(c) => s.push(c),
+                None => return Err(open.error("unterminated string")),
+            }
+        }
+    }
+
+    // Reads what follows a `#`: a set, a discarded form or a `#uuid`.
+    fn read_dispatch(&mut self, position: Position) -> Result<Option<Read>> {
+        match self.chars.peek() {
+            Some('{') => {
+                self.next();
+                let edn = Edn::Set(self.read_until('}', position)?);
+                Ok(Some(Read::Form(Form { edn, position })))
+            }
+            Some('_') => {
+                self.next();
+                match self.read()? {
+                    Some(Read::Form(_)) => self.read(),
+                    Some(Read::Close(c, position)) => Err(position.unexpected(c)),
+                    None => Err(position.error("expected a form to discard after #_")),
+                }
+            }
+            _ => {
+                let tag = self.read_token();
+                if tag != "uuid" {
+                    return Err(position.error(format!("unsupported tag #{}", tag)));
+                }
+                let uuid = match self.read()? {
+                    Some(Read::Form(Form {
+                        edn: Edn::String(s),
+                        position,
+                    })) => uuid::Uuid::parse_str(&s)
+                        .map_err(|_| position.error(format!("invalid uuid {:?}", s)))?,
+                    _ => return Err(position.error("expected a string after #uuid")),
+                };
+                Ok(Some(Read::Form(Form {
+                    edn: Edn::Uuid(uuid),
+                    position,
+                })))
+            }
+        }
+    }
+
+    // Reads the characters up to the next delimiter.
+    fn read_token(&mut self) -> String {
+        let mut token = String::new();
+        while let Some(&c) = self.chars.peek() {
+            if c.is_whitespace() || matches!(c, ',' | ';' | '"' | '(' | ')' | '[' | ']' | '{' | '}')
+            {
+                break;
+            }
+            token.push(c);
+            self.next();
+        }
+        token
+    }
+
+    fn read_atom(&self, token: &str, position: Position) -> Result<Edn> {
+        let numeric = |s: &str| s.starts_with(|c: char| c.is_ascii_digit());
+        Ok(match token {
+            "nil" => Edn::Nil,
+            "true" => Edn::Boolean(true),
+            "false" => Edn::Boolean(false),
+            _ if numeric(token) || numeric(token.trim_start_matches(['+', '-'])) => {
+                let digits = token.strip_prefix('+').unwrap_or(token);
+                let i = digits
+                    .parse()
+                    .map_err(|_| position.error(format!("invalid number {}", token)))?;
+                Edn::Integer(i)
+            }
+            _ => Edn::Symbol(token.to_string()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{edn::*, error::Error};
+
+    fn edn(input: &str) -> Edn {
+        parse(input).unwrap().edn
+    }
+
+    fn parse_error(input: &str) -> (usize, usize) {
+        match parse(input) {
+            Err(Error::Parse { line, column, .. }) => (line, column),
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn read() {
+        assert_eq!(edn("nil"), Edn::Nil);
+        assert_eq!(edn(" -12 "), Edn::Integer(-12));
+        assert_eq!(
+            edn("\"a \\\"b\\\"\\n\""),
+            Edn::String("a \"b\"\n".to_string())
+        );
+        assert_eq!(edn(":artist/name"), Edn::Keyword(Key::from(":artist/name")));
+        assert_eq!(edn("..."), Edn::Symbol("...".to_string()));
+        assert_eq!(
+            edn("#uuid \"f81d4fae-7dec-11d0-a765-00a0c91e6bf6\""),
+            Edn::Uuid(uuid::Uuid::parse_str("f81d4fae-7dec-11d0-a765-00a0c91e6bf6").unwrap())
+        );
+
+        let form = parse("; pattern\n[*, {:a 1} #_ :b\n (:c)]").unwrap();
+        assert_eq!(form.position, Position { line: 2, column: 1 });
+        let forms = match form.edn {
+            Edn::Vector(forms) => forms,
+            edn => panic!("expected a vector, got {:?}", edn),
+        };
+        assert_eq!(forms.len(), 3);
+        assert_eq!(forms[0].edn, Edn::Symbol("*".to_string()));
+        assert!(matches!(&forms[1].edn, Edn::Map(entries) if entries.len() == 1));
+        assert_eq!(forms[2].position, Position { line: 3, column: 2 });
+        assert!(matches!(edn("#{1 2}"), Edn::Set(forms) if forms.len() == 2));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(parse_error(""), (1, 1));
+        assert_eq!(parse_error("[:a\n  (:b]"), (2, 6));
+        assert_eq!(parse_error("[:a\n \"b]"), (2, 2));
+        assert_eq!(parse_error("{:a}"), (1, 1));
+        assert_eq!(parse_error(":a :b"), (1, 4));
+        assert_eq!(parse_error("#inst \"2020\""), (1, 1));
+        assert_eq!(parse_error(&"[".repeat(200_000)), (1, MAX_DEPTH + 1));
+        assert_eq!(parse_error(&"#_ ".repeat(200_000)), (1, 3 * MAX_DEPTH + 1));
+    }
+}
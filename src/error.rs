@@ -0,0 +1,184 @@
+use std::{fmt, time::SystemTime};
+
+use crate::{
+    database_snapshot::Identity, datom::Datom, schema::ValueType, AttributeId, EntityId, Key, V,
+};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    // The same fact was both asserted and retracted within a single transaction.
+    Conflict {
+        e: EntityId,
+        a: AttributeId,
+        v: V,
+    },
+    // Two different values were asserted for a cardinality-one attribute of the same entity.
+    Cardinality {
+        e: EntityId,
+        a: AttributeId,
+        values: (V, V),
+    },
+    // The value of a unique attribute is already held by another entity.
+    Unique {
+        datom: Datom,
+        existing: EntityId,
+    },
+    // A tempid upserted to two different entities.
+    TempidConflict {
+        tempid: String,
+        entities: (EntityId, EntityId),
+    },
+    // A tempid was only used as the value of a reference, so it names no entity.
+    TempidNotAnEntity(String),
+    // A tempid named a partition that can't be encoded in an entity id, or
+    // `:db.part/tx`, whose entities are made only by transacting.
+    InvalidPartition(EntityId),
+    // Transactions can only be applied to the current database, not an as-of view.
+    AsOf,
+    // No entity matches the identity.
+    NotFound(Identity),
+    // A lookup ref used an attribute that isn't `:db/unique`.
+    NotUnique(Key),
+    // The datom's attribute is not installed in the schema.
+    UnknownAttribute {
+        datom: Datom,
+    },
+    // The datom's value does not match its attribute's `:db/valueType`.
+    InvalidValue {
+        datom: Datom,
+        expected: ValueType,
+    },
+    // A `:db/cas` found a value other than the one it expected.
+    Cas {
+        e: EntityId,
+        a: AttributeId,
+        expected: Option<V>,
+        current: Option<V>,
+    },
+    // The operation only applies to cardinality-one attributes.
+    NotCardinalityOne(AttributeId),
+    // No transaction function is installed on the entity a `TxOp::Call` names.
+    UnknownFunction(Identity),
+    // A transaction function can't be installed under an ident that already
+    // names another kind of entity.
+    IdentInUse(Key),
+    // Transaction function calls nested deeper than `transaction::MAX_CALL_DEPTH`,
+    // the last of them being to the identified function.
+    CallDepth(Identity),
+    // A transaction's `:db/txInstant` is earlier than the previous transaction's.
+    TxInstant {
+        instant: SystemTime,
+        previous: SystemTime,
+    },
+    // `:db/txInstant` was asserted or retracted on an entity other than the
+    // transaction's own.
+    TxInstantEntity(EntityId),
+    // Text could not be read as EDN, or didn't describe what it was read as.
+    Parse {
+        message: String,
+        line: usize,
+        column: usize,
+    },
+    // A query's inputs don't match the arguments it was given, or it finds a
+    // variable none of its clauses bind.
+    Query(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Conflict { e, a, v } => write!(
+                f,
+                "conflicting assertion and retraction of [{:?} {:?} {:?}]",
+                e, a, v
+            ),
+            Error::Cardinality { e, a, values } => write!(
+                f,
+                "both {:?} and {:?} asserted for cardinality-one attribute {:?} of {:?}",
+                values.0, values.1, a, e
+            ),
+            Error::Unique { datom, existing } => write!(
+                f,
+                "unique value {:?} of attribute {:?} is already held by {:?} in {:?}",
+                datom.v, datom.a, existing, datom
+            ),
+            Error::TempidConflict { tempid, entities } => write!(
+                f,
+                "tempid {} resolves to both {:?} and {:?}",
+                tempid, entities.0, entities.1
+            ),
+            Error::TempidNotAnEntity(tempid) => {
+                write!(
+                    f,
+                    "tempid {} is only used as a value, never as an entity",
+                    tempid
+                )
+            }
+            Error::InvalidPartition(partition) => write!(f, "invalid partition {:?}", partition),
+            Error::AsOf => write!(f, "cannot transact against an as-of snapshot"),
+            Error::NotFound(ident) => write!(f, "no entity matches {:?}", ident),
+            Error::NotUnique(attr) => {
+                write!(f, "lookup ref attribute {} is not unique", attr.0)
+            }
+            Error::UnknownAttribute { datom } => {
+                write!(f, "unknown attribute {:?} in {:?}", datom.a, datom)
+            }
+            Error::InvalidValue { datom, expected } => write!(
+                f,
+                "value {:?} is not a {} in {:?}",
+                datom.v,
+                expected.ident(),
+                datom
+            ),
+            Error::Cas {
+                e,
+                a,
+                expected,
+                current,
+            } => write!(
+                f,
+                "compare-and-swap of attribute {:?} of {:?} expected {:?} but found {:?}",
+                a, e, expected, current
+            ),
+            Error::NotCardinalityOne(a) => {
+                write!(f, "attribute {:?} is not cardinality one", a)
+            }
+            Error::UnknownFunction(function) => {
+                write!(f, "no transaction function is installed on {:?}", function)
+            }
+            Error::IdentInUse(ident) => {
+                write!(
+                    f,
+                    "{} already names an entity that is not a transaction function",
+                    ident.0
+                )
+            }
+            Error::CallDepth(function) => write!(
+                f,
+                "transaction function calls nested deeper than {} calling {:?}",
+                crate::transaction::MAX_CALL_DEPTH,
+                function
+            ),
+            Error::TxInstant { instant, previous } => write!(
+                f,
+                "transaction instant {:?} is earlier than the previous transaction's, {:?}",
+                instant, previous
+            ),
+            Error::TxInstantEntity(e) => {
+                write!(f, ":db/txInstant of {:?} is not the transaction's own", e)
+            }
+            Error::Parse {
+                message,
+                line,
+                column,
+            } => {
+                write!(f, "{} at line {}, column {}", message, line, column)
+            }
+            Error::Query(message) => write!(f, "invalid query: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -4,7 +4,27 @@ use immutable_chunkmap::set::Set;
 
 use crate::{datom::Datom, AttributeId, EntityId, Maximum, Minimum, TransactionId, SIZE, V};
 
-use super::datom::{AEVTDatom, EAVTDatom};
+use super::datom::{AEVTDatom, AVETDatom, EAVTDatom, VAETDatom};
+
+// Coalesces a stream of datoms down to the facts that currently hold. Every
+// index orders by `t` and then `added` after (e, a, v), so all the datoms for a
+// fact are adjacent and the last of them decides whether it still holds.
+pub(crate) fn current<'a>(
+    datoms: impl Iterator<Item = &'a Datom>,
+) -> impl Iterator<Item = &'a Datom> {
+    let mut datoms = datoms.peekable();
+    std::iter::from_fn(move || loop {
+        let mut latest = datoms.next()?;
+        while let Some(datom) = datoms
+            .next_if(|next| next.e == latest.e && next.a == latest.a && next.v == latest.v)
+        {
+            latest = datom;
+        }
+        if latest.added {
+            return Some(latest);
+        }
+    })
+}
 
 #[derive(Shrinkwrap, Clone)]
 pub(crate) struct EAVTIndex {
@@ -32,12 +52,14 @@ impl EAVTIndex {
             AttributeId::minimum(),
             V::minimum(),
             TransactionId::minimum(),
+            false,
         ));
         let max = EAVTDatom::from(Datom::new(
             e,
             AttributeId::maximum(),
             V::maximum(),
             TransactionId::maximum(),
+            true,
         ));
         let range = self
             .index
@@ -46,8 +68,8 @@ impl EAVTIndex {
         range
     }
     pub(crate) fn select_ea(&self, e: EntityId, a: AttributeId) -> impl Iterator<Item = &Datom> {
-        let min = EAVTDatom::from(Datom::new(e, a, V::minimum(), TransactionId::minimum()));
-        let max = EAVTDatom::from(Datom::new(e, a, V::maximum(), TransactionId::maximum()));
+        let min = EAVTDatom::from(Datom::new(e, a, V::minimum(), TransactionId::minimum(), false));
+        let max = EAVTDatom::from(Datom::new(e, a, V::maximum(), TransactionId::maximum(), true));
         let range = self
             .index
             .range(Bound::Included(min), Bound::Included(max))
@@ -60,8 +82,8 @@ impl EAVTIndex {
         a: AttributeId,
         v: &V,
     ) -> impl Iterator<Item = &Datom> {
-        let min = EAVTDatom::from(Datom::new(e, a, v.clone(), TransactionId::minimum()));
-        let max = EAVTDatom::from(Datom::new(e, a, v.clone(), TransactionId::maximum()));
+        let min = EAVTDatom::from(Datom::new(e, a, v.clone(), TransactionId::minimum(), false));
+        let max = EAVTDatom::from(Datom::new(e, a, v.clone(), TransactionId::maximum(), true));
         let range = self
             .index
             .range(Bound::Included(min), Bound::Included(max))
@@ -75,8 +97,8 @@ impl EAVTIndex {
         v: &V,
         t: TransactionId,
     ) -> impl Iterator<Item = &Datom> {
-        let min = EAVTDatom::from(Datom::new(e, a, v.clone(), t));
-        let max = EAVTDatom::from(Datom::new(e, a, v.clone(), t));
+        let min = EAVTDatom::from(Datom::new(e, a, v.clone(), t, false));
+        let max = EAVTDatom::from(Datom::new(e, a, v.clone(), t, true));
         let range = self
             .index
             .range(Bound::Included(min), Bound::Included(max))
@@ -114,12 +136,14 @@ impl AEVTIndex {
             a,
             V::minimum(),
             TransactionId::minimum(),
+            false,
         ));
         let max = AEVTDatom::from(Datom::new(
             EntityId::maximum(),
             a,
             V::maximum(),
             TransactionId::maximum(),
+            true,
         ));
         let range = self
             .index
@@ -128,8 +152,8 @@ impl AEVTIndex {
         range
     }
     pub(crate) fn select_ae(&self, a: AttributeId, e: EntityId) -> impl Iterator<Item = &Datom> {
-        let min = AEVTDatom::from(Datom::new(e, a, V::minimum(), TransactionId::minimum()));
-        let max = AEVTDatom::from(Datom::new(e, a, V::maximum(), TransactionId::maximum()));
+        let min = AEVTDatom::from(Datom::new(e, a, V::minimum(), TransactionId::minimum(), false));
+        let max = AEVTDatom::from(Datom::new(e, a, V::maximum(), TransactionId::maximum(), true));
         let range = self
             .index
             .range(Bound::Included(min), Bound::Included(max))
@@ -142,8 +166,8 @@ impl AEVTIndex {
         e: EntityId,
         v: &V,
     ) -> impl Iterator<Item = &Datom> {
-        let min = AEVTDatom::from(Datom::new(e, a, v.clone(), TransactionId::minimum()));
-        let max = AEVTDatom::from(Datom::new(e, a, v.clone(), TransactionId::maximum()));
+        let min = AEVTDatom::from(Datom::new(e, a, v.clone(), TransactionId::minimum(), false));
+        let max = AEVTDatom::from(Datom::new(e, a, v.clone(), TransactionId::maximum(), true));
         let range = self
             .index
             .range(Bound::Included(min), Bound::Included(max))
@@ -157,8 +181,8 @@ impl AEVTIndex {
         v: &V,
         t: TransactionId,
     ) -> impl Iterator<Item = &Datom> {
-        let min = AEVTDatom::from(Datom::new(e, a, v.clone(), t));
-        let max = AEVTDatom::from(Datom::new(e, a, v.clone(), t));
+        let min = AEVTDatom::from(Datom::new(e, a, v.clone(), t, false));
+        let max = AEVTDatom::from(Datom::new(e, a, v.clone(), t, true));
         let range = self
             .index
             .range(Bound::Included(min), Bound::Included(max))
@@ -166,3 +190,252 @@ impl AEVTIndex {
         range
     }
 }
+
+#[derive(Shrinkwrap, Clone)]
+pub(crate) struct AVETIndex {
+    index: Set<AVETDatom, SIZE>,
+}
+
+impl AVETIndex {
+    pub(crate) fn new() -> AVETIndex {
+        AVETIndex { index: Set::new() }
+    }
+
+    pub(crate) fn insert(self, datom: Datom) -> AVETIndex {
+        AVETIndex {
+            index: self.index.insert(AVETDatom::from(datom)).0,
+        }
+    }
+
+    pub(crate) fn scan(&self) -> impl Iterator<Item = &Datom> {
+        self.index.into_iter().map(|datom| &datom.datom)
+    }
+
+    pub(crate) fn select_av(&self, a: AttributeId, v: &V) -> impl Iterator<Item = &Datom> {
+        self.select_av_range(a, Bound::Included(v), Bound::Included(v))
+    }
+    // Selects the datoms of attribute `a` whose value lies between `start` and `end`.
+    pub(crate) fn select_av_range(
+        &self,
+        a: AttributeId,
+        start: Bound<&V>,
+        end: Bound<&V>,
+    ) -> impl DoubleEndedIterator<Item = &Datom> {
+        let min = match start {
+            Bound::Included(v) => Bound::Included(AVETDatom::from(Datom::new(
+                EntityId::minimum(),
+                a,
+                v.clone(),
+                TransactionId::minimum(),
+                false,
+            ))),
+            Bound::Excluded(v) => Bound::Excluded(AVETDatom::from(Datom::new(
+                EntityId::maximum(),
+                a,
+                v.clone(),
+                TransactionId::maximum(),
+                true,
+            ))),
+            Bound::Unbounded => Bound::Included(AVETDatom::from(Datom::new(
+                EntityId::minimum(),
+                a,
+                V::minimum(),
+                TransactionId::minimum(),
+                false,
+            ))),
+        };
+        let max = match end {
+            Bound::Included(v) => Bound::Included(AVETDatom::from(Datom::new(
+                EntityId::maximum(),
+                a,
+                v.clone(),
+                TransactionId::maximum(),
+                true,
+            ))),
+            Bound::Excluded(v) => Bound::Excluded(AVETDatom::from(Datom::new(
+                EntityId::minimum(),
+                a,
+                v.clone(),
+                TransactionId::minimum(),
+                false,
+            ))),
+            Bound::Unbounded => Bound::Included(AVETDatom::from(Datom::new(
+                EntityId::maximum(),
+                a,
+                V::maximum(),
+                TransactionId::maximum(),
+                true,
+            ))),
+        };
+        self.index.range(min, max).map(|datom| &datom.datom)
+    }
+    pub(crate) fn select_ave(
+        &self,
+        a: AttributeId,
+        v: &V,
+        e: EntityId,
+    ) -> impl Iterator<Item = &Datom> {
+        let min = AVETDatom::from(Datom::new(e, a, v.clone(), TransactionId::minimum(), false));
+        let max = AVETDatom::from(Datom::new(e, a, v.clone(), TransactionId::maximum(), true));
+        self.index
+            .range(Bound::Included(min), Bound::Included(max))
+            .map(|datom| &datom.datom)
+    }
+    pub(crate) fn select_avet(
+        &self,
+        a: AttributeId,
+        v: &V,
+        e: EntityId,
+        t: TransactionId,
+    ) -> impl Iterator<Item = &Datom> {
+        let min = AVETDatom::from(Datom::new(e, a, v.clone(), t, false));
+        let max = AVETDatom::from(Datom::new(e, a, v.clone(), t, true));
+        self.index
+            .range(Bound::Included(min), Bound::Included(max))
+            .map(|datom| &datom.datom)
+    }
+}
+
+// Only holds datoms whose value is a reference (`V::EntityId`), keyed by the
+// referenced entity so that reverse navigation doesn't need a full scan.
+#[derive(Shrinkwrap, Clone)]
+pub(crate) struct VAETIndex {
+    index: Set<VAETDatom, SIZE>,
+}
+
+impl VAETIndex {
+    pub(crate) fn new() -> VAETIndex {
+        VAETIndex { index: Set::new() }
+    }
+
+    pub(crate) fn insert(self, datom: Datom) -> VAETIndex {
+        match datom.v {
+            V::EntityId(_) => VAETIndex {
+                index: self.index.insert(VAETDatom::from(datom)).0,
+            },
+            _ => self,
+        }
+    }
+
+    pub(crate) fn scan(&self) -> impl Iterator<Item = &Datom> {
+        self.index.into_iter().map(|datom| &datom.datom)
+    }
+
+    pub(crate) fn select_v(&self, v: EntityId) -> impl Iterator<Item = &Datom> {
+        let min = VAETDatom::from(Datom::new(
+            EntityId::minimum(),
+            AttributeId::minimum(),
+            V::EntityId(v),
+            TransactionId::minimum(),
+            false,
+        ));
+        let max = VAETDatom::from(Datom::new(
+            EntityId::maximum(),
+            AttributeId::maximum(),
+            V::EntityId(v),
+            TransactionId::maximum(),
+            true,
+        ));
+        self.index
+            .range(Bound::Included(min), Bound::Included(max))
+            .map(|datom| &datom.datom)
+    }
+    pub(crate) fn select_va(&self, v: EntityId, a: AttributeId) -> impl Iterator<Item = &Datom> {
+        let min = VAETDatom::from(Datom::new(
+            EntityId::minimum(),
+            a,
+            V::EntityId(v),
+            TransactionId::minimum(),
+            false,
+        ));
+        let max = VAETDatom::from(Datom::new(
+            EntityId::maximum(),
+            a,
+            V::EntityId(v),
+            TransactionId::maximum(),
+            true,
+        ));
+        self.index
+            .range(Bound::Included(min), Bound::Included(max))
+            .map(|datom| &datom.datom)
+    }
+    pub(crate) fn select_vae(
+        &self,
+        v: EntityId,
+        a: AttributeId,
+        e: EntityId,
+    ) -> impl Iterator<Item = &Datom> {
+        let min = VAETDatom::from(Datom::new(e, a, V::EntityId(v), TransactionId::minimum(), false));
+        let max = VAETDatom::from(Datom::new(e, a, V::EntityId(v), TransactionId::maximum(), true));
+        self.index
+            .range(Bound::Included(min), Bound::Included(max))
+            .map(|datom| &datom.datom)
+    }
+    pub(crate) fn select_vaet(
+        &self,
+        v: EntityId,
+        a: AttributeId,
+        e: EntityId,
+        t: TransactionId,
+    ) -> impl Iterator<Item = &Datom> {
+        let min = VAETDatom::from(Datom::new(e, a, V::EntityId(v), t, false));
+        let max = VAETDatom::from(Datom::new(e, a, V::EntityId(v), t, true));
+        self.index
+            .range(Bound::Included(min), Bound::Included(max))
+            .map(|datom| &datom.datom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::ops::Bound;
+
+    use crate::{
+        datom::Datom,
+        indexes::{AVETIndex, VAETIndex},
+        V,
+    };
+
+    #[test]
+    fn avet_range() {
+        let mut avet = AVETIndex::new();
+        for e in 0..10 {
+            avet = avet.insert(Datom::new(e, 1, V::I64(e * 10), 1, true));
+            avet = avet.insert(Datom::new(e, 2, V::I64(e), 1, true));
+        }
+
+        let es = |datoms: Vec<&Datom>| datoms.iter().map(|d| d.e).collect::<Vec<_>>();
+        assert_eq!(es(avet.select_av(1, &V::I64(30)).collect()), vec![3]);
+        assert_eq!(es(avet.select_ave(1, &V::I64(30), 4).collect()), Vec::<i64>::new());
+        assert_eq!(
+            es(avet
+                .select_av_range(1, Bound::Included(&V::I64(20)), Bound::Excluded(&V::I64(50)))
+                .collect()),
+            vec![2, 3, 4]
+        );
+        assert_eq!(
+            es(avet
+                .select_av_range(1, Bound::Excluded(&V::I64(70)), Bound::Unbounded)
+                .collect()),
+            vec![8, 9]
+        );
+        assert_eq!(avet.select_av_range(2, Bound::Unbounded, Bound::Unbounded).count(), 10);
+    }
+
+    #[test]
+    fn vaet_only_holds_references() {
+        let mut vaet = VAETIndex::new();
+        vaet = vaet.insert(Datom::new(1, 10, V::EntityId(100), 1, true));
+        vaet = vaet.insert(Datom::new(2, 10, V::EntityId(100), 1, true));
+        vaet = vaet.insert(Datom::new(2, 11, V::EntityId(100), 1, true));
+        vaet = vaet.insert(Datom::new(3, 10, V::EntityId(101), 1, true));
+        vaet = vaet.insert(Datom::new(3, 12, V::I64(100), 1, true));
+
+        assert_eq!(vaet.scan().count(), 4);
+        assert_eq!(vaet.select_v(100).count(), 3);
+        let es: Vec<_> = vaet.select_va(100, 10).map(|d| d.e).collect();
+        assert_eq!(es, vec![1, 2]);
+        assert_eq!(vaet.select_vae(101, 10, 3).count(), 1);
+        assert_eq!(vaet.select_vaet(101, 10, 3, 2).count(), 0);
+    }
+}
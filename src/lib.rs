@@ -6,7 +6,13 @@ extern crate shrinkwraprs;
 
 pub mod database_snapshot;
 pub mod datom;
+pub mod edn;
+pub mod error;
+pub mod partition;
 pub mod pull;
+pub mod query;
+pub mod schema;
+pub mod transaction;
 mod indexes;
 
 pub type EntityId = i64;
@@ -15,9 +21,15 @@ pub type TransactionId = i64;
 
 const SIZE: usize = 512;
 
-#[derive(Shrinkwrap, Clone, Debug)]
+#[derive(Shrinkwrap, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Key(pub String);
 
+impl From<&str> for Key {
+    fn from(key: &str) -> Key {
+        Key(key.to_string())
+    }
+}
+
 pub trait Minimum {
     fn minimum() -> Self;
 }
@@ -30,27 +42,39 @@ pub trait Maximum {
 // these'll need to be updated if that changes.
 impl Minimum for i64 {
     fn minimum() -> i64 {
-        std::i64::MIN
+        i64::MIN
     }
 }
 
 impl Maximum for i64 {
     fn maximum() -> i64 {
-        std::i64::MAX
+        i64::MAX
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum V {
     MinimumValue,
     String(String),
     EntityId(EntityId),
     Uuid(uuid::Uuid),
     I64(i64),
-    Key,
+    Key(Key),
+    Boolean(bool),
+    Instant(std::time::SystemTime),
     MaximumValue,
 }
 
+impl V {
+    // The referenced entity, if this is a reference.
+    pub fn entity_id(&self) -> Option<EntityId> {
+        match self {
+            V::EntityId(e) => Some(*e),
+            _ => None,
+        }
+    }
+}
+
 impl Minimum for V {
     fn minimum() -> V {
         V::MinimumValue
@@ -1,5 +1,10 @@
 
-use lilith::{database_snapshot::DatabaseSnapshot, datom::Datom, V, pull::{Pattern, AttrSpec}, Key};
+use lilith::{
+    database_snapshot::DatabaseSnapshot,
+    schema::{DB_CARDINALITY, DB_IDENT, DB_VALUE_TYPE},
+    transaction::TxOp,
+    Key, V,
+};
 
 fn main() {
     // use arrow::datatypes::{UnionMode, DataType, Field, Schema};
@@ -19,34 +24,56 @@ fn main() {
     let mut snapshot = DatabaseSnapshot::new();
     let mut snapshot1: Option<DatabaseSnapshot> = None;
 
-    for eid in 0..5 {
-        for aid in 0..5 {
+    let schema = (0..5)
+        .flat_map(|attr| {
+            let tempid = format!("attr-{}", attr);
+            let ident = Key(format!(":fiddle/attr-{}", attr));
+            let value_type = Key::from(":db.type/long");
+            let cardinality = Key::from(":db.cardinality/many");
+            vec![
+                TxOp::add(tempid.as_str(), DB_IDENT, V::Key(ident)),
+                TxOp::add(tempid.as_str(), DB_VALUE_TYPE, V::Key(value_type)),
+                TxOp::add(tempid.as_str(), DB_CARDINALITY, V::Key(cardinality)),
+            ]
+        })
+        .collect();
+    let report = snapshot.transact(schema).unwrap();
+    let aids: Vec<_> = (0..5).map(|attr| report.tempids[&format!("attr-{}", attr)]).collect();
+    snapshot = report.db_after;
+
+    let mut eids = vec![];
+    for entity in 0..5 {
+        let mut tx_data = vec![];
+        for aid in &aids {
             for v in 0..5 {
-                let datom = Datom::new(eid, aid, V::I64(v), 1);
-                snapshot = snapshot.insert(datom);
+                tx_data.push(TxOp::add("entity", *aid, V::I64(v)));
             }
         }
-        if eid == 1 {
+        let report = snapshot.transact(tx_data).unwrap();
+        eids.push(report.tempids["entity"]);
+        snapshot = report.db_after;
+        if entity == 1 {
             snapshot1 = Some(snapshot.clone());
         }
     }
+    let (aid, eid) = (aids[3], eids[3]);
 
     println!("====== snapshot @ 5 ===");
 
     snapshot
-        .select_a(3)
+        .select_a(aid)
         .for_each(|datom| println!("select a: {:?}", datom));
 
     snapshot
-        .select_ae(3, 3)
+        .select_ae(aid, eid)
         .for_each(|datom| println!("select ae: {:?}", datom));
 
     snapshot
-        .select_aev(3, 3, &V::I64(3))
+        .select_aev(aid, eid, &V::I64(3))
         .for_each(|datom| println!("select aev: {:?}", datom));
 
     snapshot
-        .select_aevt(3, 3, &V::I64(3), 1)
+        .select_aevt(aid, eid, &V::I64(3), 5)
         .for_each(|datom| println!("select aevt: {:?}", datom));
 
     if let Some(snapshot1) = snapshot1 {
@@ -57,19 +84,19 @@ fn main() {
             .for_each(|datom| println!("scan aevt: {:?}", datom));
 
         snapshot1
-            .select_a(3)
+            .select_a(aid)
             .for_each(|datom| println!("select a: {:?}", datom));
 
         snapshot1
-            .select_ae(3, 3)
+            .select_ae(aid, eid)
             .for_each(|datom| println!("select ae: {:?}", datom));
 
         snapshot1
-            .select_aev(3, 3, &V::I64(3))
+            .select_aev(aid, eid, &V::I64(3))
             .for_each(|datom| println!("select aev: {:?}", datom));
 
         snapshot1
-            .select_aevt(3, 3, &V::I64(3), 1)
+            .select_aevt(aid, eid, &V::I64(3), 5)
             .for_each(|datom| println!("select aevt: {:?}", datom));
     }
 }
@@ -0,0 +1,54 @@
+use crate::{EntityId, TransactionId};
+
+// Entity ids carry the partition they were allocated in above their low
+// `PARTITION_SHIFT` bits, so each partition occupies its own id range and its
+// entities cluster together in EAVT. A partition is itself an entity of
+// `:db.part/db`, and its entity id is the number encoded in the high bits.
+pub const PARTITION_SHIFT: u32 = 42;
+
+pub const PART_DB: EntityId = 0;
+pub const PART_TX: EntityId = 1;
+pub const PART_USER: EntityId = 2;
+
+// Builds the id of the `index`th entity of `partition`.
+pub const fn entity_id(partition: EntityId, index: i64) -> EntityId {
+    (partition << PARTITION_SHIFT) | index
+}
+
+// The entity reifying transaction `t`, in `:db.part/tx`.
+pub const fn tx_entity(t: TransactionId) -> EntityId {
+    entity_id(PART_TX, t)
+}
+
+// The partition entity `e` was allocated in.
+pub fn partition(e: EntityId) -> EntityId {
+    e >> PARTITION_SHIFT
+}
+
+// The position of `e` within its partition.
+pub fn index(e: EntityId) -> i64 {
+    e & ((1 << PARTITION_SHIFT) - 1)
+}
+
+// Whether `partition` can be encoded in an entity id.
+pub fn is_valid(partition: EntityId) -> bool {
+    (0..1 << (63 - PARTITION_SHIFT)).contains(&partition)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::partition::*;
+
+    #[test]
+    fn encode_decode() {
+        let e = entity_id(PART_USER, 17);
+        assert_eq!(partition(e), PART_USER);
+        assert_eq!(index(e), 17);
+        assert_eq!(entity_id(PART_DB, 17), 17);
+        assert!(entity_id(PART_TX, 0) < entity_id(PART_USER, 0));
+        let last = (1 << (63 - PARTITION_SHIFT)) - 1;
+        assert!(is_valid(last));
+        assert!(!is_valid(last + 1));
+        assert!(entity_id(last, index(-1)) > 0);
+    }
+}
@@ -1,10 +1,22 @@
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+};
 
-use im::HashMap;
-
-use crate::{Key, V};
+use crate::{
+    database_snapshot::{DatabaseSnapshot, Value},
+    datom::Datom,
+    edn::{self, Edn, Form},
+    error::{Error, Result},
+    schema::{self, Cardinality, ValueType},
+    AttributeId, EntityId, Key, V,
+};
 
+// The keyword a pattern uses for an entity's own id.
+const DB_ID: &str = ":db/id";
 
 // pattern             = [attr-spec+]
+#[derive(Debug, PartialEq)]
 pub struct Pattern {
     specs: Vec<AttrSpec>,
 }
@@ -21,37 +33,52 @@ impl Pattern {
 type AttrName = Key;
 
 // recursion-limit     = positive-number | '...'
+#[derive(Debug, PartialEq)]
 pub enum RecursionLimit {
     Bounded(u32),
     Unbounded,
 }
 
 // attr-spec           = attr-name | wildcard | map-spec | attr-expr
+#[derive(Debug, PartialEq)]
 pub enum AttrSpec {
     Wildcard,
     Attribute(Attribute),
     Recursion(Recursion),
+    MapSpec(MapSpec),
 }
 
-// pub enum PatternOrRecursionLimit {
-//     Pattern(Pattern),
-//     RecursionLimit(RecursionLimit),
-// }
+#[derive(Debug, PartialEq)]
+pub enum PatternOrRecursionLimit {
+    Pattern(Pattern),
+    RecursionLimit(RecursionLimit),
+}
 
 // map-spec            = { ((attr-name | attr-expr) (pattern | recursion-limit))+ }
-// pub struct MapSpec {
-//     data: HashMap<Attribute, PatternOrRecursionLimit>
-// }
+// The entries are kept in the order they were written, as attributes have no
+// ordering of their own to key a map by.
+#[derive(Debug, PartialEq)]
+pub struct MapSpec {
+    data: Vec<(Attribute, PatternOrRecursionLimit)>,
+}
+
+impl MapSpec {
+    pub fn new(data: Vec<(Attribute, PatternOrRecursionLimit)>) -> Self {
+        MapSpec { data }
+    }
+}
 
 // attr-option         = as-expr | limit-expr | default-expr
+#[derive(Debug, PartialEq)]
 pub struct Attribute {
-    name: Key,
-    rename: Option<V>,
+    name: AttrName,
+    rename: Option<Key>,
     limit: Option<u32>,
     default: Option<V>,
     reverse: bool,
 }
 
+#[derive(Debug, PartialEq)]
 pub struct Recursion {
     // The attribute to traverse and recur upon. Must be a reference-type attribute.
     target: Attribute,
@@ -66,51 +93,961 @@ impl Recursion {
     pub fn new(target: Attribute, limit: RecursionLimit) -> Self {
         Recursion{target, limit, pattern: None}
     }
+
+    // The pattern to pull the entities reached with, in place of the one the
+    // recursion is nested in.
+    pub fn pattern(mut self, pattern: Pattern) -> Self {
+        self.pattern = Some(pattern);
+        self
+    }
 }
 
 impl Attribute {
+    // A name in the reverse form, e.g. `:artist/_country`, yields the reverse
+    // of the attribute it names.
     pub fn new(name: Key) -> Attribute {
-        Attribute { name, rename: None, limit: Some(1000), default: None, reverse: false}
+        let (name, reverse) = match name.rsplit_once("/_") {
+            Some((namespace, name)) => (Key(format!("{}/{}", namespace, name)), true),
+            None => (name, false),
+        };
+        Attribute { name, rename: None, limit: Some(1000), default: None, reverse}
     }
 
     pub fn reverse(mut self) -> Self {
         self.reverse = true;
         self
     } 
+
+    // as-expr: the key the values are returned under.
+    pub fn rename(mut self, key: Key) -> Self {
+        self.rename = Some(key);
+        self
+    }
+
+    // limit-expr: at most `limit` values of a cardinality-many (or reverse)
+    // attribute are returned. Defaults to 1000.
+    pub fn limit(mut self, limit: u32) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    // limit-expr with `nil`: every value is returned.
+    pub fn unlimited(mut self) -> Self {
+        self.limit = None;
+        self
+    }
+
+    // default-expr: the value returned when the entity has none.
+    pub fn default(mut self, default: V) -> Self {
+        self.default = Some(default);
+        self
+    }
+
+    // The key this attribute's values are returned under.
+    fn key(&self) -> Key {
+        match (&self.rename, self.reverse) {
+            (Some(rename), _) => rename.clone(),
+            (None, false) => self.name.clone(),
+            (None, true) => match self.name.rsplit_once('/') {
+                Some((namespace, name)) => Key(format!("{}/_{}", namespace, name)),
+                None => self.name.clone(),
+            },
+        }
+    }
+}
+
+// Reads a pattern written in the pull grammar above, e.g.
+// `[:artist/name {:artist/country [*]} (:track/name :as "n" :limit 5)]`.
+impl FromStr for Pattern {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Pattern> {
+        parse_pattern(&edn::parse(s)?)
+    }
+}
+
+fn parse_pattern(form: &Form) -> Result<Pattern> {
+    match &form.edn {
+        Edn::Vector(forms) => {
+            let specs = forms.iter().map(parse_attr_spec).collect::<Result<_>>()?;
+            Ok(Pattern::new(specs))
+        }
+        _ => Err(form.error("expected a pattern vector")),
+    }
+}
+
+fn parse_attr_spec(form: &Form) -> Result<AttrSpec> {
+    match &form.edn {
+        Edn::Symbol(symbol) if symbol == "*" => Ok(AttrSpec::Wildcard),
+        Edn::Keyword(_) | Edn::List(_) => Ok(AttrSpec::Attribute(parse_attribute(form)?)),
+        Edn::Map(entries) => {
+            let data = entries
+                .iter()
+                .map(|(attribute, nested)| {
+                    let nested = match &nested.edn {
+                        Edn::Vector(_) => PatternOrRecursionLimit::Pattern(parse_pattern(nested)?),
+                        _ => {
+                            let limit = parse_recursion_limit(nested)?;
+                            PatternOrRecursionLimit::RecursionLimit(limit)
+                        }
+                    };
+                    Ok((parse_attribute(attribute)?, nested))
+                })
+                .collect::<Result<_>>()?;
+            Ok(AttrSpec::MapSpec(MapSpec::new(data)))
+        }
+        _ => Err(form.error("expected an attribute name, attribute expression, * or map spec")),
+    }
+}
+
+// attr-name | (attr-name attr-option+)
+fn parse_attribute(form: &Form) -> Result<Attribute> {
+    let forms = match &form.edn {
+        Edn::Keyword(name) => return Ok(Attribute::new(name.clone())),
+        Edn::List(forms) => forms,
+        _ => return Err(form.error("expected an attribute name or attribute expression")),
+    };
+    let mut attribute = match forms.first().map(|form| &form.edn) {
+        Some(Edn::Keyword(name)) => Attribute::new(name.clone()),
+        _ => return Err(form.error("expected an attribute expression to start with a name")),
+    };
+    for option in forms[1..].chunks(2) {
+        let value = match option {
+            [_, value] => value,
+            _ => return Err(option[0].error("expected a value for the attribute option")),
+        };
+        attribute = match (&option[0].edn, &value.edn) {
+            (Edn::Keyword(key), Edn::Keyword(rename)) if **key == ":as" => {
+                attribute.rename(rename.clone())
+            }
+            (Edn::Keyword(key), Edn::String(rename) | Edn::Symbol(rename)) if **key == ":as" => {
+                attribute.rename(Key(rename.clone()))
+            }
+            (Edn::Keyword(key), Edn::Integer(limit)) if **key == ":limit" => {
+                let limit = u32::try_from(*limit)
+                    .map_err(|_| value.error("expected a non-negative limit"))?;
+                attribute.limit(limit)
+            }
+            (Edn::Keyword(key), Edn::Nil) if **key == ":limit" => attribute.unlimited(),
+            (Edn::Keyword(key), _) if **key == ":default" => match value.v() {
+                Some(default) => attribute.default(default),
+                None => return Err(value.error("expected a scalar default value")),
+            },
+            _ => return Err(option[0].error("expected :as, :limit or :default")),
+        };
+    }
+    Ok(attribute)
+}
+
+// recursion-limit     = positive-number | '...'
+fn parse_recursion_limit(form: &Form) -> Result<RecursionLimit> {
+    match &form.edn {
+        Edn::Symbol(symbol) if symbol == "..." => Ok(RecursionLimit::Unbounded),
+        Edn::Integer(limit) if *limit > 0 => match u32::try_from(*limit) {
+            Ok(limit) => Ok(RecursionLimit::Bounded(limit)),
+            Err(_) => Err(form.error("recursion limit is too large")),
+        },
+        _ => Err(form.error("expected a pattern, a positive recursion limit or ...")),
+    }
+}
+
+// A pattern with its attribute names resolved against a snapshot, so that it
+// can be applied to any number of entities.
+struct Plan<'a> {
+    steps: Vec<Step<'a>>,
+}
+
+enum Step<'a> {
+    // Every attribute of the entity, under its ident.
+    Wildcard,
+    Id {
+        key: Key,
+    },
+    Attribute {
+        key: Key,
+        // None when the name isn't an installed attribute, in which case only
+        // the default (if any) is returned.
+        attribute: Option<&'a schema::Attribute>,
+        spec: &'a Attribute,
+        // How the entities a reference attribute points at are pulled. When
+        // None, they are returned as `{:db/id n}`.
+        nested: Option<Nested<'a>>,
+    },
+}
+
+enum Nested<'a> {
+    // Applies `plan` to each entity reached.
+    Pattern(Plan<'a>),
+    // Follows the attribute again from each entity reached, up to `limit`
+    // times. `plan` is applied to those entities, or if None, the plan the
+    // step belongs to. `id` numbers the recursions of the whole pattern, so
+    // that their depths can be tracked independently.
+    Recursion {
+        id: usize,
+        limit: Option<u32>,
+        plan: Option<Plan<'a>>,
+    },
+}
+
+impl<'a> Plan<'a> {
+    fn new(db: &'a DatabaseSnapshot, pattern: &'a Pattern) -> Plan<'a> {
+        Plan::with_recursions(db, pattern, &mut 0)
+    }
+
+    // The plan components are pulled with when a wildcard reaches them: a
+    // wildcard of their own, which goes on to pull their components in turn.
+    fn components() -> Plan<'a> {
+        Plan { steps: vec![Step::Wildcard] }
+    }
+
+    fn with_recursions(
+        db: &'a DatabaseSnapshot,
+        pattern: &'a Pattern,
+        recursions: &mut usize,
+    ) -> Plan<'a> {
+        let mut steps = Vec::new();
+        for spec in &pattern.specs {
+            match spec {
+                AttrSpec::Wildcard => steps.push(Step::Wildcard),
+                AttrSpec::Attribute(spec) => steps.push(Step::plan(db, spec, None)),
+                AttrSpec::Recursion(Recursion { target, limit, pattern }) => {
+                    let nested = Nested::recursion(db, limit, pattern.as_ref(), recursions);
+                    steps.push(Step::plan(db, target, Some(nested)));
+                }
+                AttrSpec::MapSpec(MapSpec { data }) => {
+                    for (spec, nested) in data {
+                        let nested = match nested {
+                            PatternOrRecursionLimit::Pattern(pattern) => {
+                                Nested::Pattern(Plan::with_recursions(db, pattern, recursions))
+                            }
+                            PatternOrRecursionLimit::RecursionLimit(limit) => {
+                                Nested::recursion(db, limit, None, recursions)
+                            }
+                        };
+                        steps.push(Step::plan(db, spec, Some(nested)));
+                    }
+                }
+            }
+        }
+        Plan { steps }
+    }
+}
+
+impl<'a> Nested<'a> {
+    fn recursion(
+        db: &'a DatabaseSnapshot,
+        limit: &RecursionLimit,
+        pattern: Option<&'a Pattern>,
+        recursions: &mut usize,
+    ) -> Nested<'a> {
+        let id = *recursions;
+        *recursions += 1;
+        let limit = match limit {
+            RecursionLimit::Bounded(limit) => Some(*limit),
+            RecursionLimit::Unbounded => None,
+        };
+        let plan = pattern.map(|pattern| Plan::with_recursions(db, pattern, recursions));
+        Nested::Recursion { id, limit, plan }
+    }
+}
+
+impl<'a> Step<'a> {
+    fn plan(db: &'a DatabaseSnapshot, spec: &'a Attribute, nested: Option<Nested<'a>>) -> Step<'a> {
+        if !spec.reverse && *spec.name == DB_ID {
+            return Step::Id { key: spec.key() };
+        }
+        let attribute = db.attribute(spec.name.clone());
+        Step::Attribute { key: spec.key(), attribute, spec, nested }
+    }
 }
 
+// An entity being pulled. Pulling the entities it refers to is done by pushing
+// frames for them onto an explicit stack rather than by recursing, so that
+// unbounded recursion can follow arbitrarily long chains of references.
+struct Frame<'p, 'a> {
+    e: EntityId,
+    plan: &'p Plan<'a>,
+    datoms: Vec<&'a Datom>,
+    // How many times each recursion has been followed to reach this entity.
+    depths: Vec<u32>,
+    map: HashMap<Key, Value>,
+    // The next step of the plan to apply.
+    next: usize,
+    // The referenced entities being pulled for the current step. Only the
+    // last is being pulled; the others wait for it to finish.
+    descents: Vec<Descent<'p, 'a>>,
+    // The plan that components are pulled with when they are reached by a
+    // wildcard.
+    components: &'p Plan<'a>,
+}
+
+struct Descent<'p, 'a> {
+    key: Key,
+    many: bool,
+    plan: &'p Plan<'a>,
+    depths: Vec<u32>,
+    // Whether the descent follows a recursion, and so has to watch for cycles.
+    recursive: bool,
+    children: std::vec::IntoIter<EntityId>,
+    values: Vec<Value>,
+}
+
+impl<'p, 'a> Frame<'p, 'a> {
+    fn new(
+        db: &'a DatabaseSnapshot,
+        e: EntityId,
+        plan: &'p Plan<'a>,
+        depths: Vec<u32>,
+        components: &'p Plan<'a>,
+    ) -> Self {
+        let datoms = db.select_e(e).collect();
+        let map = HashMap::new();
+        Frame { e, plan, datoms, depths, map, next: 0, descents: Vec::new(), components }
+    }
+
+    // The current datoms of attribute `a`.
+    fn datoms(&self, a: AttributeId) -> &[&'a Datom] {
+        let start = self.datoms.partition_point(|datom| datom.a < a);
+        let end = self.datoms.partition_point(|datom| datom.a <= a);
+        &self.datoms[start..end]
+    }
+
+    // Applies `step`, either adding its result to the map or starting
+    // descents into the entities it refers to.
+    fn apply(&mut self, db: &'a DatabaseSnapshot, step: &'p Step<'a>) {
+        match step {
+            // Components are pulled in full, in place of their ids.
+            Step::Wildcard => {
+                self.map.insert(Key::from(DB_ID), Value::V(V::EntityId(self.e)));
+                for group in self.datoms.chunk_by(|a, b| a.a == b.a) {
+                    let Some(attribute) = db.attribute(group[0].a) else {
+                        continue;
+                    };
+                    if attribute.is_component && attribute.value_type == ValueType::Ref {
+                        self.descents.push(Descent {
+                            key: attribute.ident.clone(),
+                            many: attribute.cardinality == Cardinality::Many,
+                            plan: self.components,
+                            depths: self.depths.clone(),
+                            recursive: true,
+                            children: group
+                                .iter()
+                                .filter_map(|datom| datom.v.entity_id())
+                                .collect::<Vec<_>>()
+                                .into_iter(),
+                            values: Vec::new(),
+                        });
+                    } else {
+                        self.map.insert(attribute.ident.clone(), collect(attribute, group, None));
+                    }
+                }
+            }
+            Step::Id { key } => {
+                self.map.insert(key.clone(), Value::V(V::EntityId(self.e)));
+            }
+            Step::Attribute { key, attribute, spec, nested } => {
+                let pulled = attribute.and_then(|attribute| {
+                    self.pull_attribute(db, key, attribute, spec, nested.as_ref())
+                });
+                match pulled {
+                    Some(Pulled::Value(value)) => {
+                        self.map.insert(key.clone(), value);
+                    }
+                    Some(Pulled::Descent(descent)) => self.descents.push(descent),
+                    None => {
+                        if let Some(default) = &spec.default {
+                            self.map.insert(key.clone(), Value::V(default.clone()));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // The values of `attribute` selected by `spec`, if the entity has any.
+    fn pull_attribute(
+        &self,
+        db: &'a DatabaseSnapshot,
+        key: &Key,
+        attribute: &schema::Attribute,
+        spec: &Attribute,
+        nested: Option<&'p Nested<'a>>,
+    ) -> Option<Pulled<'p, 'a>> {
+        let nested = nested.filter(|_| attribute.value_type == ValueType::Ref);
+        if spec.reverse {
+            let referrers: Vec<EntityId> = limit(db.select_va(self.e, attribute.id), spec.limit)
+                .map(|datom| datom.e)
+                .collect();
+            if referrers.is_empty() {
+                return None;
+            }
+            Some(match self.descend(key, true, nested, referrers.clone()) {
+                Some(descent) => Pulled::Descent(descent),
+                None => Pulled::Value(Value::Vec(referrers.into_iter().map(reference).collect())),
+            })
+        } else {
+            let datoms = self.datoms(attribute.id);
+            if datoms.is_empty() {
+                return None;
+            }
+            let many = attribute.cardinality == Cardinality::Many;
+            let children: Vec<EntityId> = limit(datoms.iter(), spec.limit)
+                .filter_map(|datom| datom.v.entity_id())
+                .collect();
+            Some(match self.descend(key, many, nested, children) {
+                Some(descent) => Pulled::Descent(descent),
+                None => Pulled::Value(collect(attribute, datoms, spec.limit)),
+            })
+        }
+    }
+
+    // The descent into `children` through a step nested with `nested`, or
+    // None if they are to be returned as references, because there is no
+    // nesting or the recursion is spent.
+    fn descend(
+        &self,
+        key: &Key,
+        many: bool,
+        nested: Option<&'p Nested<'a>>,
+        children: Vec<EntityId>,
+    ) -> Option<Descent<'p, 'a>> {
+        let (plan, depths, recursive) = match nested? {
+            Nested::Pattern(plan) => (plan, self.depths.clone(), false),
+            Nested::Recursion { id, limit, plan } => {
+                let depth = self.depths.get(*id).copied().unwrap_or(0);
+                if limit.is_some_and(|limit| depth >= limit) {
+                    return None;
+                }
+                let mut depths = self.depths.clone();
+                if depths.len() <= *id {
+                    depths.resize(id + 1, 0);
+                }
+                depths[*id] = depth + 1;
+                (plan.as_ref().unwrap_or(self.plan), depths, true)
+            }
+        };
+        Some(Descent {
+            key: key.clone(),
+            many,
+            plan,
+            depths,
+            recursive,
+            children: children.into_iter(),
+            values: Vec::new(),
+        })
+    }
+}
+
+enum Pulled<'p, 'a> {
+    Value(Value),
+    Descent(Descent<'p, 'a>),
+}
+
+// Pulls the entity at the bottom of `stack`, and everything it refers to.
+// Entities reached by recursion are checked against those on the stack, which
+// are the entities being pulled on the way to them, so that a cycle yields
+// `{:db/id n}` for the entity it returns to instead of being followed forever.
+fn run<'p, 'a>(db: &'a DatabaseSnapshot, mut stack: Vec<Frame<'p, 'a>>) -> Value {
+    let mut pulling: HashSet<EntityId> = stack.iter().map(|frame| frame.e).collect();
+    loop {
+        let top = stack.len() - 1;
+        let child = stack[top].descents.last_mut().and_then(|descent| descent.children.next());
+        if let Some(child) = child {
+            let descent = stack[top].descents.last().unwrap();
+            if descent.recursive && pulling.contains(&child) {
+                stack[top].descents.last_mut().unwrap().values.push(reference(child));
+            } else {
+                let depths = descent.depths.clone();
+                let frame = Frame::new(db, child, descent.plan, depths, stack[top].components);
+                pulling.insert(child);
+                stack.push(frame);
+            }
+            continue;
+        }
+        // A cardinality-one descent limited to no children leaves its key out.
+        if let Some(mut descent) = stack[top].descents.pop() {
+            let value = match descent.many {
+                true => Some(Value::Vec(descent.values)),
+                false => descent.values.pop(),
+            };
+            if let Some(value) = value {
+                stack[top].map.insert(descent.key, value);
+            }
+            continue;
+        }
+        let frame = &mut stack[top];
+        if let Some(step) = frame.plan.steps.get(frame.next) {
+            frame.next += 1;
+            frame.apply(db, step);
+            continue;
+        }
+        let frame = stack.pop().unwrap();
+        pulling.remove(&frame.e);
+        let value = Value::Map(frame.map);
+        match stack.last_mut() {
+            Some(parent) => parent.descents.last_mut().unwrap().values.push(value),
+            None => return value,
+        }
+    }
+}
+
+// A referenced entity, as returned when no pattern is given for it.
+fn reference(e: EntityId) -> Value {
+    Value::Map(HashMap::from([(Key::from(DB_ID), Value::V(V::EntityId(e)))]))
+}
+
+fn limit<T>(values: impl Iterator<Item = T>, limit: Option<u32>) -> impl Iterator<Item = T> {
+    values.take(limit.map_or(usize::MAX, |limit| limit as usize))
+}
+
+// The values of `attribute` held by `datoms` (of which there is at least one)
+// as returned by pull: a single value for cardinality one, and a vector of at
+// most `max` values for cardinality many.
+fn collect(attribute: &schema::Attribute, datoms: &[&Datom], max: Option<u32>) -> Value {
+    let value = |datom: &&Datom| match (attribute.value_type, &datom.v) {
+        (ValueType::Ref, V::EntityId(e)) => reference(*e),
+        (_, v) => Value::V(v.clone()),
+    };
+    match attribute.cardinality {
+        Cardinality::One => value(&datoms[0]),
+        Cardinality::Many => Value::Vec(limit(datoms.iter().map(value), max).collect()),
+    }
+}
+
+// Pulls `pattern` for entity `e`.
+pub(crate) fn pull(db: &DatabaseSnapshot, pattern: &Pattern, e: EntityId) -> Value {
+    let plan = Plan::new(db, pattern);
+    let components = Plan::components();
+    run(db, vec![Frame::new(db, e, &plan, Vec::new(), &components)])
+}
+
+// Pulls `pattern` for each of `es`, planning it once. Each entity's datoms are
+// found by its own EAVT seek; the ids are sorted only so that an entity asked
+// for more than once is pulled once and copied. The results are returned in the
+// order they were asked for.
+pub(crate) fn pull_many(db: &DatabaseSnapshot, pattern: &Pattern, es: &[EntityId]) -> Vec<Value> {
+    let plan = Plan::new(db, pattern);
+    let components = Plan::components();
+    let mut order: Vec<usize> = (0..es.len()).collect();
+    order.sort_by_key(|i| es[*i]);
+    let mut pulled: Vec<Option<Value>> = vec![None; es.len()];
+    for group in order.chunk_by(|i, j| es[*i] == es[*j]) {
+        let value = run(db, vec![Frame::new(db, es[group[0]], &plan, Vec::new(), &components)]);
+        for i in &group[1..] {
+            pulled[*i] = Some(value.clone());
+        }
+        pulled[group[0]] = Some(value);
+    }
+    pulled.into_iter().map(|value| value.unwrap()).collect()
+}
+
+#[cfg(test)]
 mod test {
-    use crate::pull::*;
+    use crate::{
+        database_snapshot::{Identity, LookupRef, Value},
+        partition::{entity_id, PART_USER},
+        error::Error,
+        pull::*,
+        EntityId,
+        schema::{
+            test::{db, COMPONENT, E, ID, LONGS, REF, STRING},
+            DB_CARDINALITY, DB_IDENT, DB_VALUE_TYPE,
+        },
+        transaction::TxOp,
+        AttributeId,
+    };
+
+    fn map(entries: Vec<(&str, Value)>) -> Value {
+        Value::Map(entries.into_iter().map(|(key, value)| (Key::from(key), value)).collect())
+    }
+
+    fn string(s: &str) -> Value {
+        Value::V(V::String(s.to_string()))
+    }
+
+    fn attr(name: &str) -> Attribute {
+        Attribute::new(Key::from(name))
+    }
 
+    #[test]
     fn test() {
-        let db_id = Key(":db/id".to_string());
         let artist_name = Key(":artist/name".to_string());
         let artist_gid = Key(":artist/gid".to_string());
         let artist_country = Key(":artist/country".to_string());
 
         let track_name = Key(":track/name".to_string());
 
-        let attribute_names = Pattern::new(
-            vec![
-                AttrSpec::Attribute(Attribute::new(artist_name)),
-                AttrSpec::Attribute(Attribute::new(artist_gid))
-            ]
+        let attribute_names = Pattern::new(vec![
+            AttrSpec::Attribute(Attribute::new(artist_name)),
+            AttrSpec::Attribute(Attribute::new(artist_gid)),
+        ]);
+        assert_eq!("[:artist/name :artist/gid]".parse(), Ok(attribute_names));
+
+        let reverse_attribute = Pattern::new(vec![AttrSpec::Attribute(
+            Attribute::new(artist_country.clone()).reverse(),
+        )]);
+        assert_eq!("[:artist/_country]".parse(), Ok(reverse_attribute));
+
+        let _map_spec = Pattern::new(vec![
+            AttrSpec::Attribute(Attribute::new(track_name)),
+            AttrSpec::Recursion(Recursion::new(
+                Attribute::new(artist_country),
+                RecursionLimit::Unbounded,
+            )),
+        ]);
+    }
+
+    #[test]
+    fn pull() {
+        let e2 = entity_id(PART_USER, 2);
+        let db = db()
+            .transact(vec![
+                TxOp::add(E, ID, V::String("ada".to_string())),
+                TxOp::add(E, STRING, V::String("Ada".to_string())),
+                TxOp::add(E, LONGS, V::I64(1)),
+                TxOp::add(E, LONGS, V::I64(2)),
+                TxOp::add(E, LONGS, V::I64(3)),
+                TxOp::add(E, REF, V::EntityId(e2)),
+                TxOp::add(e2, STRING, V::String("Grace".to_string())),
+            ])
+            .unwrap()
+            .db_after;
+
+        let pattern = Pattern::new(vec![
+            AttrSpec::Attribute(attr(":db/id").rename(Key::from(":id"))),
+            AttrSpec::Attribute(attr(":test/string")),
+            AttrSpec::Attribute(attr(":test/longs").limit(2)),
+            AttrSpec::Attribute(attr(":test/ref")),
+            AttrSpec::Attribute(attr(":test/email").default(V::String("none".to_string()))),
+            AttrSpec::Attribute(attr(":test/unknown")),
+        ]);
+        assert_eq!(
+            db.pull(&pattern, E).unwrap(),
+            map(vec![
+                (":id", Value::V(V::EntityId(E))),
+                (":test/string", string("Ada")),
+                (":test/longs", Value::Vec(vec![Value::V(V::I64(1)), Value::V(V::I64(2))])),
+                (":test/ref", Value::Vec(vec![map(vec![(":db/id", Value::V(V::EntityId(e2)))])])),
+                (":test/email", string("none")),
+            ])
         );
 
-        let reverse_attribute = Pattern::new(
-            vec![
-                AttrSpec::Attribute(Attribute::new(artist_country).reverse())
-            ]
+        let reverse = Pattern::new(vec![
+            AttrSpec::Attribute(attr(":test/_ref")),
+            AttrSpec::Attribute(attr(":test/ref").reverse().rename(Key::from(":referrers"))),
+        ]);
+        let referrers = Value::Vec(vec![map(vec![(":db/id", Value::V(V::EntityId(E)))])]);
+        assert_eq!(
+            db.pull(&reverse, e2).unwrap(),
+            map(vec![(":test/_ref", referrers.clone()), (":referrers", referrers)])
         );
+        assert_eq!(db.pull(&reverse, E).unwrap(), map(vec![]));
+
+        let wildcard = Pattern::new(vec![AttrSpec::Wildcard]);
+        assert_eq!(
+            db.pull(&wildcard, e2).unwrap(),
+            map(vec![(":db/id", Value::V(V::EntityId(e2))), (":test/string", string("Grace"))])
+        );
+
+        // The entity may be given by a lookup ref.
+        let ada = LookupRef::new(Key::from(":test/id"), V::String("ada".to_string()));
+        let name = Pattern::new(vec![AttrSpec::Attribute(attr(":test/string"))]);
+        assert_eq!(db.pull(&name, ada).unwrap(), map(vec![(":test/string", string("Ada"))]));
+        let nobody = Identity::from(LookupRef::new(Key::from(":test/id"), V::String("?".into())));
+        assert_eq!(db.pull(&name, nobody.clone()), Err(Error::NotFound(nobody.clone())));
+        assert_eq!(db.pull_many(&name, [nobody.clone()]), Err(Error::NotFound(nobody)));
+    }
+
+    #[test]
+    fn pull_many() {
+        let e2 = entity_id(PART_USER, 2);
+        let e3 = entity_id(PART_USER, 3);
+        let db = db()
+            .transact(vec![
+                TxOp::add(E, STRING, V::String("Ada".to_string())),
+                TxOp::add(e2, STRING, V::String("Grace".to_string())),
+            ])
+            .unwrap()
+            .db_after;
 
-        let map_spec = Pattern::new(
-            vec![
-                AttrSpec::Attribute(Attribute::new(track_name)),
-                AttrSpec::Recursion(
-                    Recursion::new()
-                )
-            ]
-        )
+        let pattern = Pattern::new(vec![
+            AttrSpec::Attribute(attr(":test/string").default(V::String("?".to_string()))),
+        ]);
+        let pulled = db.pull_many(&pattern, [e3, e2, E, e2]).unwrap();
+        let names: Vec<_> = ["?", "Grace", "Ada", "Grace"]
+            .iter()
+            .map(|name| map(vec![(":test/string", string(name))]))
+            .collect();
+        assert_eq!(pulled, names);
+        assert_eq!(db.pull_many(&pattern, Vec::<EntityId>::new()), Ok(vec![]));
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn recursion() {
+        let es: Vec<EntityId> = (1..=4).map(|index| entity_id(PART_USER, index)).collect();
+        let mut ops: Vec<TxOp> = es
+            .iter()
+            .zip(["a", "b", "c", "d"])
+            .map(|(e, name)| TxOp::add(*e, STRING, V::String(name.to_string())))
+            .collect();
+        ops.extend(es.windows(2).map(|pair| TxOp::add(pair[0], REF, V::EntityId(pair[1]))));
+        let db = db().transact(ops).unwrap().db_after;
+
+        let id = |e| map(vec![(":db/id", Value::V(V::EntityId(e)))]);
+        let node = |name, refs| map(vec![(":test/string", string(name)), (":test/ref", refs)]);
+        let pattern = |limit| {
+            Pattern::new(vec![
+                AttrSpec::Attribute(attr(":test/string")),
+                AttrSpec::Recursion(Recursion::new(attr(":test/ref"), limit)),
+            ])
+        };
+
+        // past the limit, entities are returned as references
+        let c = node("c", Value::Vec(vec![id(es[3])]));
+        let b = node("b", Value::Vec(vec![c]));
+        assert_eq!(
+            db.pull(&pattern(RecursionLimit::Bounded(2)), es[0]).unwrap(),
+            node("a", Value::Vec(vec![b]))
+        );
+
+        // a cycle stops at the first entity it revisits
+        let db = db.transact(vec![TxOp::add(es[3], REF, V::EntityId(es[0]))]).unwrap().db_after;
+        let d = node("d", Value::Vec(vec![id(es[0])]));
+        let c = node("c", Value::Vec(vec![d]));
+        let b = node("b", Value::Vec(vec![c]));
+        assert_eq!(
+            db.pull(&pattern(RecursionLimit::Unbounded), es[0]).unwrap(),
+            node("a", Value::Vec(vec![b]))
+        );
+
+        // the recursion's own pattern applies to the entities it reaches
+        let pattern = Pattern::new(vec![
+            AttrSpec::Attribute(attr(":test/string")),
+            AttrSpec::Recursion(
+                Recursion::new(attr(":test/_ref"), RecursionLimit::Unbounded)
+                    .pattern(Pattern::new(vec![AttrSpec::Attribute(attr(":db/id"))])),
+            ),
+        ]);
+        assert_eq!(
+            db.pull(&pattern, es[1]).unwrap(),
+            map(vec![(":test/string", string("b")), (":test/_ref", Value::Vec(vec![id(es[0])]))])
+        );
+    }
+
+    #[test]
+    fn unbounded_recursion_is_not_limited_by_the_stack() {
+        let depth = 10_000;
+        let es: Vec<EntityId> = (1..=depth).map(|index| entity_id(PART_USER, index)).collect();
+        let ops = es.windows(2).map(|pair| TxOp::add(pair[0], REF, V::EntityId(pair[1])));
+        let db = db().transact(ops.collect()).unwrap().db_after;
+
+        let pattern = Pattern::new(vec![AttrSpec::Recursion(Recursion::new(
+            attr(":test/ref"),
+            RecursionLimit::Unbounded,
+        ))]);
+        let pulled = db.pull(&pattern, es[0]).unwrap();
+        let mut value = &pulled;
+        let mut reached = 1;
+        while let Value::Map(map) = value {
+            match map.get(&Key::from(":test/ref")) {
+                Some(Value::Vec(refs)) => value = &refs[0],
+                _ => break,
+            }
+            reached += 1;
+        }
+        assert_eq!(reached, depth);
+        let copy = pulled.clone();
+        assert!(copy == pulled);
+        copy.dispose();
+        pulled.dispose();
+    }
+
+    #[test]
+    fn pull_many_copies_deep_values_asked_for_twice() {
+        let depth = 10_000;
+        let es: Vec<EntityId> = (1..=depth).map(|index| entity_id(PART_USER, index)).collect();
+        let ops = es.windows(2).map(|pair| TxOp::add(pair[0], REF, V::EntityId(pair[1])));
+        let db = db().transact(ops.collect()).unwrap().db_after;
+
+        let pattern = Pattern::new(vec![AttrSpec::Recursion(Recursion::new(
+            attr(":test/ref"),
+            RecursionLimit::Unbounded,
+        ))]);
+        let mut pulled = db.pull_many(&pattern, vec![es[0], es[1], es[0]]).unwrap();
+        assert_eq!(pulled.len(), 3);
+        assert!(pulled[0] == pulled[2]);
+        assert!(pulled[0] != pulled[1]);
+        for value in pulled.drain(..) {
+            value.dispose();
+        }
+    }
+
+    #[test]
+    fn map_spec() {
+        let e2 = entity_id(PART_USER, 2);
+        let e3 = entity_id(PART_USER, 3);
+        let db = db()
+            .transact(vec![
+                TxOp::add(E, STRING, V::String("a".to_string())),
+                TxOp::add(E, REF, V::EntityId(e2)),
+                TxOp::add(E, REF, V::EntityId(e3)),
+                TxOp::add(e2, STRING, V::String("b".to_string())),
+                TxOp::add(e2, REF, V::EntityId(e3)),
+                TxOp::add(e3, STRING, V::String("c".to_string())),
+            ])
+            .unwrap()
+            .db_after;
+
+        let name = |name| map(vec![(":test/string", string(name))]);
+        let strings = || Pattern::new(vec![AttrSpec::Attribute(attr(":test/string"))]);
+        let pattern = Pattern::new(vec![
+            AttrSpec::Attribute(attr(":test/string")),
+            AttrSpec::MapSpec(MapSpec::new(vec![(
+                attr(":test/ref").limit(1),
+                PatternOrRecursionLimit::Pattern(strings()),
+            )])),
+        ]);
+        assert_eq!(
+            db.pull(&pattern, E).unwrap(),
+            map(vec![(":test/string", string("a")), (":test/ref", Value::Vec(vec![name("b")]))])
+        );
+
+        let reverse = Pattern::new(vec![AttrSpec::MapSpec(MapSpec::new(vec![(
+            attr(":test/_ref").rename(Key::from(":parents")),
+            PatternOrRecursionLimit::Pattern(strings()),
+        )]))]);
+        assert_eq!(
+            db.pull(&reverse, e3).unwrap(),
+            map(vec![(":parents", Value::Vec(vec![name("a"), name("b")]))])
+        );
+
+        let recursive = Pattern::new(vec![
+            AttrSpec::Attribute(attr(":test/string")),
+            AttrSpec::MapSpec(MapSpec::new(vec![(
+                attr(":test/ref"),
+                PatternOrRecursionLimit::RecursionLimit(RecursionLimit::Bounded(1)),
+            )])),
+        ]);
+        let b = map(vec![
+            (":test/string", string("b")),
+            (":test/ref", Value::Vec(vec![map(vec![(":db/id", Value::V(V::EntityId(e3)))])])),
+        ]);
+        assert_eq!(
+            db.pull(&recursive, E).unwrap(),
+            map(vec![(":test/string", string("a")), (":test/ref", Value::Vec(vec![b, name("c")]))])
+        );
+    }
+
+    #[test]
+    fn map_spec_on_cardinality_one() {
+        const ONE: AttributeId = 106;
+        let e2 = entity_id(PART_USER, 2);
+        let keyword = |ident: &str| V::Key(Key::from(ident));
+        let db = db()
+            .transact(vec![
+                TxOp::add(ONE, DB_IDENT, keyword(":test/one")),
+                TxOp::add(ONE, DB_VALUE_TYPE, keyword(":db.type/ref")),
+                TxOp::add(ONE, DB_CARDINALITY, keyword(":db.cardinality/one")),
+            ])
+            .unwrap()
+            .db_after
+            .transact(vec![
+                TxOp::add(E, ONE, V::EntityId(e2)),
+                TxOp::add(e2, STRING, V::String("b".to_string())),
+            ])
+            .unwrap()
+            .db_after;
+
+        let pattern = |limit: &str| -> Pattern {
+            format!("[{{(:test/one{}) [:test/string]}}]", limit).parse().unwrap()
+        };
+        assert_eq!(
+            db.pull(&pattern(""), E).unwrap(),
+            map(vec![(":test/one", map(vec![(":test/string", string("b"))]))])
+        );
+        assert_eq!(db.pull(&pattern(" :limit 0"), E).unwrap(), map(vec![]));
+    }
+
+    #[test]
+    fn parse() {
+        let pattern = "[:artist/name {:artist/country [*]} (:track/name :as \"n\" :limit 5)]";
+        let pattern: Pattern = pattern.parse().unwrap();
+        assert_eq!(
+            pattern,
+            Pattern::new(vec![
+                AttrSpec::Attribute(attr(":artist/name")),
+                AttrSpec::MapSpec(MapSpec::new(vec![(
+                    attr(":artist/country"),
+                    PatternOrRecursionLimit::Pattern(Pattern::new(vec![AttrSpec::Wildcard])),
+                )])),
+                AttrSpec::Attribute(attr(":track/name").rename(Key::from("n")).limit(5)),
+            ])
+        );
+
+        let pattern: Pattern = "[(:db/id :as :id) {(:person/_friends :limit nil) ...}
+            {:person/friends 2} (:person/age :default 0)]"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            pattern,
+            Pattern::new(vec![
+                AttrSpec::Attribute(attr(":db/id").rename(Key::from(":id"))),
+                AttrSpec::MapSpec(MapSpec::new(vec![(
+                    attr(":person/friends").reverse().unlimited(),
+                    PatternOrRecursionLimit::RecursionLimit(RecursionLimit::Unbounded),
+                )])),
+                AttrSpec::MapSpec(MapSpec::new(vec![(
+                    attr(":person/friends"),
+                    PatternOrRecursionLimit::RecursionLimit(RecursionLimit::Bounded(2)),
+                )])),
+                AttrSpec::Attribute(attr(":person/age").default(V::I64(0))),
+            ])
+        );
+
+        let error = |input: &str| match input.parse::<Pattern>() {
+            Err(Error::Parse { line, column, .. }) => (line, column),
+            _ => panic!("expected {} not to parse", input),
+        };
+        assert_eq!(error(":artist/name"), (1, 1));
+        assert_eq!(error("[:artist/name\n \"name\"]"), (2, 2));
+        assert_eq!(error("[(:artist/name :as)]"), (1, 16));
+        assert_eq!(error("[(:artist/name :limit -1)]"), (1, 23));
+        assert_eq!(error("[{:artist/country 0}]"), (1, 19));
+        assert_eq!(error("[:artist/name"), (1, 1));
+    }
+
+    #[test]
+    fn wildcard_pulls_components() {
+        let part = entity_id(PART_USER, 2);
+        let subpart = entity_id(PART_USER, 3);
+        let other = entity_id(PART_USER, 4);
+        let db = db()
+            .transact(vec![
+                TxOp::add(E, COMPONENT, V::EntityId(part)),
+                TxOp::add(E, REF, V::EntityId(other)),
+                TxOp::add(part, STRING, V::String("part".to_string())),
+                TxOp::add(part, COMPONENT, V::EntityId(subpart)),
+                TxOp::add(subpart, STRING, V::String("subpart".to_string())),
+                TxOp::add(other, STRING, V::String("other".to_string())),
+            ])
+            .unwrap()
+            .db_after;
+
+        let id = |e| (":db/id", Value::V(V::EntityId(e)));
+        let subpart = map(vec![id(subpart), (":test/string", string("subpart"))]);
+        let part = map(vec![
+            id(part),
+            (":test/string", string("part")),
+            (":test/component", Value::Vec(vec![subpart])),
+        ]);
+        let wildcard: Pattern = "[*]".parse().unwrap();
+        assert_eq!(
+            db.pull(&wildcard, E).unwrap(),
+            map(vec![
+                id(E),
+                (":test/ref", Value::Vec(vec![map(vec![id(other)])])),
+                (":test/component", Value::Vec(vec![part])),
+            ])
+        );
+    }
+}
@@ -0,0 +1,895 @@
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+};
+
+use crate::{
+    database_snapshot::{DatabaseSnapshot, HistorySnapshot},
+    datom::Datom,
+    edn::{self, Edn, Form},
+    error::{Error, Result},
+    partition,
+    schema::ValueType,
+    AttributeId, EntityId, V,
+};
+
+// query               = [:find find-spec (:in in-binding+)? :where data-pattern+]
+#[derive(Debug, PartialEq)]
+pub struct Query {
+    find: Find,
+    inputs: Vec<Input>,
+    clauses: Vec<Clause>,
+}
+
+impl Query {
+    pub fn new(find: Find, inputs: Vec<Input>, clauses: Vec<Clause>) -> Self {
+        Query {
+            find,
+            inputs,
+            clauses,
+        }
+    }
+}
+
+// variable            = a symbol starting with '?'
+pub type Var = String;
+
+// find-spec           = find-rel | find-coll | find-tuple | find-scalar
+#[derive(Debug, PartialEq)]
+pub enum Find {
+    // ?a ?b
+    Relation(Vec<Var>),
+    // [?a ...]
+    Collection(Var),
+    // [?a ?b]
+    Tuple(Vec<Var>),
+    // ?a .
+    Scalar(Var),
+}
+
+// in-binding          = src-var | ?a | [?a ...] | [?a ?b] | [[?a ?b]]
+#[derive(Debug, PartialEq)]
+pub enum Input {
+    Source,
+    Scalar(Var),
+    Collection(Var),
+    Tuple(Vec<Var>),
+    Relation(Vec<Var>),
+}
+
+impl Input {
+    fn vars(&self) -> Vec<&Var> {
+        match self {
+            Input::Source => vec![],
+            Input::Scalar(var) | Input::Collection(var) => vec![var],
+            Input::Tuple(vars) | Input::Relation(vars) => vars.iter().collect(),
+        }
+    }
+}
+
+// The values given for the inputs other than the source, in order.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Arg {
+    Scalar(V),
+    Collection(Vec<V>),
+    Tuple(Vec<V>),
+    Relation(Vec<Vec<V>>),
+}
+
+// term                = variable | constant | '_'
+#[derive(Clone, Debug, PartialEq)]
+pub enum Term {
+    Var(Var),
+    Constant(V),
+    Blank,
+}
+
+// data-pattern        = [src-var? e a v tx? added?]
+// Trailing terms may be left out, which is the same as giving '_' for them.
+// An entity, attribute or transaction may be given as an entity id or a
+// keyword ident, and so may the value of a reference attribute. A transaction
+// may also be given as its `t`.
+#[derive(Debug, PartialEq)]
+pub struct Clause {
+    e: Term,
+    a: Term,
+    v: Term,
+    tx: Term,
+    added: Term,
+}
+
+impl Clause {
+    pub fn new(e: Term, a: Term, v: Term) -> Self {
+        Clause {
+            e,
+            a,
+            v,
+            tx: Term::Blank,
+            added: Term::Blank,
+        }
+    }
+
+    pub fn tx(mut self, tx: Term) -> Self {
+        self.tx = tx;
+        self
+    }
+
+    pub fn added(mut self, added: Term) -> Self {
+        self.added = added;
+        self
+    }
+
+    fn terms(&self) -> [&Term; 5] {
+        [&self.e, &self.a, &self.v, &self.tx, &self.added]
+    }
+}
+
+// Entities, attributes and transactions are returned as `V::EntityId`, and
+// a transaction by the id of its entity. Relations and collections are sets,
+// returned in order.
+#[derive(Clone, Debug, PartialEq)]
+pub enum QueryResult {
+    Relation(Vec<Vec<V>>),
+    Collection(Vec<V>),
+    Tuple(Option<Vec<V>>),
+    Scalar(Option<V>),
+}
+
+// What a query runs against: the facts of a snapshot as of its horizon, or
+// its history.
+pub(crate) trait Source {
+    // The snapshot idents and attributes are resolved against.
+    fn schema(&self) -> &DatabaseSnapshot;
+    fn datoms(&self, lookup: Lookup) -> Box<dyn Iterator<Item = &Datom> + '_>;
+}
+
+impl Source for DatabaseSnapshot {
+    fn schema(&self) -> &DatabaseSnapshot {
+        self
+    }
+
+    fn datoms(&self, lookup: Lookup) -> Box<dyn Iterator<Item = &Datom> + '_> {
+        match lookup {
+            Lookup::Eav(e, a, v) => Box::new(self.select_eav(e, a, &v)),
+            Lookup::Ea(e, a) => Box::new(self.select_ea(e, a)),
+            Lookup::E(e) => Box::new(self.select_e(e)),
+            Lookup::Av(a, v) => Box::new(self.select_av(a, &v)),
+            Lookup::Va(v, a) => Box::new(self.select_va(v, a)),
+            Lookup::A(a) => Box::new(self.select_a(a)),
+            Lookup::V(v) => Box::new(self.select_v(v)),
+            Lookup::All => Box::new(self.scan_eavt()),
+        }
+    }
+}
+
+impl Source for HistorySnapshot {
+    fn schema(&self) -> &DatabaseSnapshot {
+        self.db()
+    }
+
+    fn datoms(&self, lookup: Lookup) -> Box<dyn Iterator<Item = &Datom> + '_> {
+        match lookup {
+            Lookup::Eav(e, a, v) => Box::new(self.select_eav(e, a, &v)),
+            Lookup::Ea(e, a) => Box::new(self.select_ea(e, a)),
+            Lookup::E(e) => Box::new(self.select_e(e)),
+            Lookup::Av(a, v) => Box::new(self.select_av(a, &v)),
+            Lookup::Va(v, a) => Box::new(self.select_va(v, a)),
+            Lookup::A(a) => Box::new(self.select_a(a)),
+            Lookup::V(v) => Box::new(self.select_v(v)),
+            Lookup::All => Box::new(self.scan_eavt()),
+        }
+    }
+}
+
+// The index range a clause is read from, given which of its entity,
+// attribute and value are known.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Lookup {
+    // EAVT
+    Eav(EntityId, AttributeId, V),
+    Ea(EntityId, AttributeId),
+    E(EntityId),
+    // AVET, for indexed attributes
+    Av(AttributeId, V),
+    // VAET, for reference attributes
+    Va(EntityId, AttributeId),
+    // AEVT
+    A(AttributeId),
+    // VAET
+    V(EntityId),
+    All,
+}
+
+impl Lookup {
+    fn plan(
+        db: &DatabaseSnapshot,
+        e: Option<EntityId>,
+        a: Option<AttributeId>,
+        v: Option<&V>,
+    ) -> Lookup {
+        let attribute = a.and_then(|a| db.attribute(a));
+        match (e, a, v) {
+            (Some(e), Some(a), Some(v)) => Lookup::Eav(e, a, v.clone()),
+            (Some(e), Some(a), None) => Lookup::Ea(e, a),
+            (Some(e), None, _) => Lookup::E(e),
+            (None, Some(a), Some(v)) if attribute.is_some_and(|attribute| attribute.indexed()) => {
+                Lookup::Av(a, v.clone())
+            }
+            (None, Some(a), Some(V::EntityId(v)))
+                if attribute.is_some_and(|attribute| attribute.value_type == ValueType::Ref) =>
+            {
+                Lookup::Va(*v, a)
+            }
+            (None, Some(a), _) => Lookup::A(a),
+            (None, None, Some(V::EntityId(v))) => Lookup::V(*v),
+            (None, None, _) => Lookup::All,
+        }
+    }
+}
+
+// Reads a query written in the grammar above, e.g.
+// `[:find ?name :in $ ?id :where [?e :user/id ?id] [?e :user/name ?name]]`.
+impl FromStr for Query {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Query> {
+        let form = edn::parse(s)?;
+        let forms = match &form.edn {
+            Edn::Vector(forms) => forms,
+            _ => return Err(form.error("expected a query vector")),
+        };
+        let mut sections: HashMap<&str, (&Form, Vec<&Form>)> = HashMap::new();
+        let mut section = None;
+        for form in forms {
+            match &form.edn {
+                Edn::Keyword(key) if matches!(key.as_str(), ":find" | ":in" | ":where") => {
+                    if sections.insert(key.as_str(), (form, vec![])).is_some() {
+                        return Err(form.error(format!("duplicate {} section", key.0)));
+                    }
+                    section = Some(key.as_str());
+                }
+                _ => match section {
+                    Some(section) => sections.get_mut(section).unwrap().1.push(form),
+                    None => return Err(form.error("expected :find")),
+                },
+            }
+        }
+        let find = match sections.get(":find") {
+            Some((keyword, forms)) => parse_find(keyword, forms)?,
+            None => return Err(form.error("expected a :find section")),
+        };
+        let inputs = match sections.get(":in") {
+            Some((_, forms)) => forms
+                .iter()
+                .map(|form| parse_input(form))
+                .collect::<Result<_>>()?,
+            None => vec![Input::Source],
+        };
+        let clauses = match sections.get(":where") {
+            Some((_, forms)) => forms
+                .iter()
+                .map(|form| parse_clause(form))
+                .collect::<Result<_>>()?,
+            None => return Err(form.error("expected a :where section")),
+        };
+        Ok(Query::new(find, inputs, clauses))
+    }
+}
+
+fn parse_var(form: &Form) -> Result<Var> {
+    match &form.edn {
+        Edn::Symbol(symbol) if symbol.starts_with('?') => Ok(symbol.clone()),
+        _ => Err(form.error("expected a variable")),
+    }
+}
+
+fn is_symbol(form: &Form, name: &str) -> bool {
+    matches!(&form.edn, Edn::Symbol(symbol) if symbol == name)
+}
+
+fn parse_find(keyword: &Form, forms: &[&Form]) -> Result<Find> {
+    match forms {
+        [] => Err(keyword.error("expected a find spec")),
+        [var, dot] if is_symbol(dot, ".") => Ok(Find::Scalar(parse_var(var)?)),
+        [form @ Form {
+            edn: Edn::Vector(vars),
+            ..
+        }] => match vars.as_slice() {
+            [var, dots] if is_symbol(dots, "...") => Ok(Find::Collection(parse_var(var)?)),
+            [] => Err(form.error("expected variables")),
+            vars => Ok(Find::Tuple(
+                vars.iter().map(parse_var).collect::<Result<_>>()?,
+            )),
+        },
+        forms => {
+            let vars = forms
+                .iter()
+                .map(|form| parse_var(form))
+                .collect::<Result<_>>()?;
+            Ok(Find::Relation(vars))
+        }
+    }
+}
+
+fn parse_input(form: &Form) -> Result<Input> {
+    match &form.edn {
+        Edn::Symbol(symbol) if symbol.starts_with('$') => parse_source(form).map(|_| Input::Source),
+        Edn::Symbol(_) => Ok(Input::Scalar(parse_var(form)?)),
+        Edn::Vector(forms) => match forms.as_slice() {
+            [var, dots] if is_symbol(dots, "...") => Ok(Input::Collection(parse_var(var)?)),
+            [Form {
+                edn: Edn::Vector(vars),
+                ..
+            }] => Ok(Input::Relation(
+                vars.iter().map(parse_var).collect::<Result<_>>()?,
+            )),
+            [] => Err(form.error("expected variables")),
+            vars => Ok(Input::Tuple(
+                vars.iter().map(parse_var).collect::<Result<_>>()?,
+            )),
+        },
+        _ => Err(form.error("expected a source, variable or binding form")),
+    }
+}
+
+fn parse_clause(form: &Form) -> Result<Clause> {
+    let forms = match &form.edn {
+        Edn::Vector(forms) => forms.as_slice(),
+        _ => return Err(form.error("expected a data pattern")),
+    };
+    let forms = match forms {
+        [source, rest @ ..] if matches!(&source.edn, Edn::Symbol(s) if s.starts_with('$')) => {
+            parse_source(source)?;
+            rest
+        }
+        forms => forms,
+    };
+    if forms.is_empty() || forms.len() > 5 {
+        return Err(form.error("expected between one and five terms"));
+    }
+    let mut terms = forms
+        .iter()
+        .map(parse_term)
+        .collect::<Result<Vec<_>>>()?
+        .into_iter();
+    let mut next = || terms.next().unwrap_or(Term::Blank);
+    let clause = Clause::new(next(), next(), next());
+    Ok(clause.tx(next()).added(next()))
+}
+
+// Queries run against a single source, so `$` is the only source variable.
+fn parse_source(form: &Form) -> Result<()> {
+    match is_symbol(form, "$") {
+        true => Ok(()),
+        false => Err(form.error("only the default source $ is supported")),
+    }
+}
+
+fn parse_term(form: &Form) -> Result<Term> {
+    match &form.edn {
+        Edn::Symbol(symbol) if symbol == "_" => Ok(Term::Blank),
+        Edn::Symbol(_) => Ok(Term::Var(parse_var(form)?)),
+        _ => match form.v() {
+            Some(v) => Ok(Term::Constant(v)),
+            None => Err(form.error("expected a variable, constant or _")),
+        },
+    }
+}
+
+// The entity a value given for an entity, attribute or transaction stands for.
+fn entity(db: &DatabaseSnapshot, v: &V) -> Option<EntityId> {
+    match v {
+        V::EntityId(e) | V::I64(e) => Some(*e),
+        V::Key(ident) => db.ent_id(ident.clone()).ok(),
+        _ => None,
+    }
+}
+
+// The entity of the transaction a value given for a transaction stands for.
+// Integers too small to be an entity id outside `:db.part/db` are taken to be
+// a transaction's `t`.
+fn transaction(db: &DatabaseSnapshot, v: &V) -> Option<EntityId> {
+    match v {
+        V::I64(t) if partition::partition(*t) == partition::PART_DB => {
+            Some(partition::tx_entity(*t))
+        }
+        v => entity(db, v),
+    }
+}
+
+// A solution to the clauses run so far, holding the value bound to each of
+// the query's variables, if any.
+type Row = Vec<Option<V>>;
+
+// Runs `query` against `source` with `args` for its inputs.
+pub(crate) fn query(source: &impl Source, query: &Query, args: Vec<Arg>) -> Result<QueryResult> {
+    let db = source.schema();
+    let mut vars: HashMap<&Var, usize> = HashMap::new();
+    let inputs = query.inputs.iter().flat_map(Input::vars);
+    let clauses = query
+        .clauses
+        .iter()
+        .flat_map(|clause| clause.terms())
+        .filter_map(|term| match term {
+            Term::Var(var) => Some(var),
+            _ => None,
+        });
+    for var in inputs.chain(clauses) {
+        let index = vars.len();
+        vars.entry(var).or_insert(index);
+    }
+
+    // Each input contributes its values, and the rows start out as every
+    // combination of them.
+    let mut rows: Vec<Row> = vec![vec![None; vars.len()]];
+    let mut args = args.into_iter();
+    for input in query.inputs.iter().filter(|input| **input != Input::Source) {
+        let arg = args
+            .next()
+            .ok_or_else(|| Error::Query(format!("no value for {:?}", input)))?;
+        let tuples = match (input, arg) {
+            (Input::Scalar(_), Arg::Scalar(v)) => vec![vec![v]],
+            (Input::Collection(_), Arg::Collection(vs)) => {
+                vs.into_iter().map(|v| vec![v]).collect()
+            }
+            (Input::Tuple(_), Arg::Tuple(tuple)) => vec![tuple],
+            (Input::Relation(_), Arg::Relation(tuples)) => tuples,
+            (input, arg) => {
+                return Err(Error::Query(format!("{:?} given for {:?}", arg, input)));
+            }
+        };
+        let input_vars = input.vars();
+        if let Some(tuple) = tuples.iter().find(|tuple| tuple.len() != input_vars.len()) {
+            return Err(Error::Query(format!("{:?} given for {:?}", tuple, input)));
+        }
+        rows = rows
+            .iter()
+            .flat_map(|row| {
+                tuples.iter().filter_map(|tuple| {
+                    let mut row = row.clone();
+                    let mut values = input_vars.iter().zip(tuple);
+                    let bound = values.all(|(var, v)| bind(&mut row, vars[var], v.clone()));
+                    bound.then_some(row)
+                })
+            })
+            .collect();
+    }
+    if args.next().is_some() {
+        return Err(Error::Query("more arguments than inputs".to_string()));
+    }
+
+    // Clauses are run in the order that has each one know as many of its
+    // terms as possible by the time it runs, so that it reads the narrowest
+    // index range.
+    let mut bound: HashSet<&Var> = query.inputs.iter().flat_map(Input::vars).collect();
+    let mut remaining: Vec<&Clause> = query.clauses.iter().collect();
+    while !remaining.is_empty() {
+        let known = |term: &Term| match term {
+            Term::Var(var) => bound.contains(var),
+            Term::Constant(_) => true,
+            Term::Blank => false,
+        };
+        let score = |clause: &Clause| {
+            [&clause.e, &clause.a, &clause.v]
+                .into_iter()
+                .filter(|term| known(term))
+                .count()
+        };
+        let next = (0..remaining.len())
+            .rev()
+            .max_by_key(|i| score(remaining[*i]))
+            .unwrap();
+        let clause = remaining.remove(next);
+        rows = run_clause(source, db, clause, &vars, rows);
+        for term in clause.terms() {
+            if let Term::Var(var) = term {
+                bound.insert(var);
+            }
+        }
+    }
+
+    let find_vars = match &query.find {
+        Find::Relation(find_vars) | Find::Tuple(find_vars) => find_vars.iter().collect(),
+        Find::Collection(var) | Find::Scalar(var) => vec![var],
+    };
+    let columns = find_vars
+        .iter()
+        .map(|var| match vars.get(var) {
+            Some(index) => Ok(*index),
+            None => Err(Error::Query(format!("{} is not bound by the query", var))),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut tuples: Vec<Vec<V>> = rows
+        .into_iter()
+        .filter_map(|row| columns.iter().map(|column| row[*column].clone()).collect())
+        .collect();
+    tuples.sort();
+    tuples.dedup();
+    let mut tuples = tuples.into_iter();
+    Ok(match query.find {
+        Find::Relation(_) => QueryResult::Relation(tuples.collect()),
+        Find::Tuple(_) => QueryResult::Tuple(tuples.next()),
+        Find::Collection(_) => QueryResult::Collection(tuples.flatten().collect()),
+        Find::Scalar(_) => QueryResult::Scalar(tuples.flatten().next()),
+    })
+}
+
+// Binds `v` to the variable at `index` of `row`, unless it is already bound to
+// a different value.
+fn bind(row: &mut Row, index: usize, v: V) -> bool {
+    match &row[index] {
+        Some(bound) => *bound == v,
+        None => {
+            row[index] = Some(v);
+            true
+        }
+    }
+}
+
+// Extends each of `rows` with each way `clause` matches the datoms of `source`.
+fn run_clause(
+    source: &impl Source,
+    db: &DatabaseSnapshot,
+    clause: &Clause,
+    vars: &HashMap<&Var, usize>,
+    rows: Vec<Row>,
+) -> Vec<Row> {
+    let mut solutions = vec![];
+    for row in rows {
+        let value = |term: &Term| match term {
+            Term::Var(var) => row[vars[var]].clone(),
+            Term::Constant(v) => Some(v.clone()),
+            Term::Blank => None,
+        };
+        // A known term that names nothing can't match any datom.
+        let (e, a, tx) = (value(&clause.e), value(&clause.a), value(&clause.tx));
+        let e = match e.map(|e| entity(db, &e)) {
+            Some(None) => continue,
+            e => e.flatten(),
+        };
+        let a = match a.map(|a| entity(db, &a)) {
+            Some(None) => continue,
+            a => a.flatten(),
+        };
+        let tx = match tx.map(|tx| transaction(db, &tx)) {
+            Some(None) => continue,
+            tx => tx.flatten(),
+        };
+        let is_ref = |a: AttributeId| {
+            db.attribute(a)
+                .is_some_and(|attribute| attribute.value_type == ValueType::Ref)
+        };
+        let v = match value(&clause.v) {
+            Some(v) if a.is_some_and(is_ref) => match entity(db, &v) {
+                Some(v) => Some(V::EntityId(v)),
+                None => continue,
+            },
+            v => v,
+        };
+        let added = value(&clause.added);
+
+        for datom in source.datoms(Lookup::plan(db, e, a, v.as_ref())) {
+            let matches = e.is_none_or(|e| datom.e == e)
+                && a.is_none_or(|a| datom.a == a)
+                && v.as_ref().is_none_or(|v| datom.v == *v)
+                && tx.is_none_or(|tx| partition::tx_entity(datom.t) == tx)
+                && added
+                    .as_ref()
+                    .is_none_or(|added| V::Boolean(datom.added) == *added);
+            if !matches {
+                continue;
+            }
+            let mut solution = row.clone();
+            let values = [
+                V::EntityId(datom.e),
+                V::EntityId(datom.a),
+                datom.v.clone(),
+                V::EntityId(partition::tx_entity(datom.t)),
+                V::Boolean(datom.added),
+            ];
+            // Variables bound before the clause ran were matched above, and may
+            // hold an ident rather than the entity id it names.
+            let bound = clause
+                .terms()
+                .into_iter()
+                .zip(values)
+                .all(|(term, v)| match term {
+                    Term::Var(var) if row[vars[var]].is_none() => bind(&mut solution, vars[var], v),
+                    _ => true,
+                });
+            if bound {
+                solutions.push(solution);
+            }
+        }
+    }
+    solutions
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        database_snapshot::{DatabaseSnapshot, Time},
+        error::Error,
+        partition::{self, entity_id, PART_USER},
+        query::*,
+        schema::test::{db, E, ID, LONGS, REF, STRING},
+        transaction::TxOp,
+        Key,
+    };
+
+    fn var(name: &str) -> Term {
+        Term::Var(name.to_string())
+    }
+
+    fn string(s: &str) -> V {
+        V::String(s.to_string())
+    }
+
+    fn keyword(ident: &str) -> V {
+        V::Key(Key::from(ident))
+    }
+
+    fn query(q: &str) -> Query {
+        q.parse().unwrap()
+    }
+
+    // E is "ada", who refers to "grace" and "alan"; "grace" refers to "alan".
+    fn people() -> DatabaseSnapshot {
+        let (grace, alan) = (entity_id(PART_USER, 2), entity_id(PART_USER, 3));
+        db().transact(vec![
+            TxOp::add(E, ID, string("ada")),
+            TxOp::add(E, STRING, string("Ada")),
+            TxOp::add(E, LONGS, V::I64(1)),
+            TxOp::add(E, REF, V::EntityId(grace)),
+            TxOp::add(E, REF, V::EntityId(alan)),
+            TxOp::add(grace, ID, string("grace")),
+            TxOp::add(grace, STRING, string("Grace")),
+            TxOp::add(grace, LONGS, V::I64(2)),
+            TxOp::add(grace, REF, V::EntityId(alan)),
+            TxOp::add(alan, ID, string("alan")),
+            TxOp::add(alan, STRING, string("Alan")),
+            TxOp::add(alan, LONGS, V::I64(2)),
+        ])
+        .unwrap()
+        .db_after
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            query(
+                "[:find ?name . :in $ ?id [?ids ...] [?a ?b] [[?c ?d]] :where [?e :test/id ?id]
+                    [$ ?e :test/string ?name _ true] [?e 101]]"
+            ),
+            Query::new(
+                Find::Scalar("?name".to_string()),
+                vec![
+                    Input::Source,
+                    Input::Scalar("?id".to_string()),
+                    Input::Collection("?ids".to_string()),
+                    Input::Tuple(vec!["?a".to_string(), "?b".to_string()]),
+                    Input::Relation(vec!["?c".to_string(), "?d".to_string()]),
+                ],
+                vec![
+                    Clause::new(var("?e"), Term::Constant(keyword(":test/id")), var("?id")),
+                    Clause::new(
+                        var("?e"),
+                        Term::Constant(keyword(":test/string")),
+                        var("?name")
+                    )
+                    .added(Term::Constant(V::Boolean(true))),
+                    Clause::new(var("?e"), Term::Constant(V::I64(101)), Term::Blank),
+                ],
+            )
+        );
+        assert_eq!(
+            query("[:find ?a ?b :where [?a _ ?b]]").find,
+            Find::Relation(vec!["?a".to_string(), "?b".to_string(),])
+        );
+        assert_eq!(
+            query("[:find [?a ...] :where [?a]]").find,
+            Find::Collection("?a".to_string())
+        );
+        assert_eq!(
+            query("[:find [?a] :where [?a]]").find,
+            Find::Tuple(vec!["?a".to_string()])
+        );
+        assert_eq!(query("[:find ?a :where [?a]]").inputs, vec![Input::Source]);
+
+        let error = |q: &str| match q.parse::<Query>() {
+            Err(Error::Parse { line, column, .. }) => (line, column),
+            other => panic!("expected a parse error, got {:?}", other),
+        };
+        assert_eq!(error("{:find ?a}"), (1, 1));
+        assert_eq!(error("[?a :find ?a :where [?a]]"), (1, 2));
+        assert_eq!(error("[:find ?a]"), (1, 1));
+        assert_eq!(error("[:find :where [?a]]"), (1, 2));
+        assert_eq!(error("[:find ?a :where\n [?a _ _ _ _ _]]"), (2, 2));
+        assert_eq!(error("[:find ?a :where [?a {}]]"), (1, 22));
+        assert_eq!(error("[:find a :where [?a]]"), (1, 8));
+        assert_eq!(error("[:find ?a :in $db :where [?a]]"), (1, 15));
+        assert_eq!(error("[:find ?a :where [$db ?a]]"), (1, 19));
+    }
+
+    #[test]
+    fn plan() {
+        let db = people();
+        let name = V::String("Ada".to_string());
+        assert_eq!(Lookup::plan(&db, Some(E), None, Some(&name)), Lookup::E(E));
+        assert_eq!(
+            Lookup::plan(&db, None, Some(STRING), Some(&name)),
+            Lookup::A(STRING)
+        );
+        assert_eq!(
+            Lookup::plan(&db, None, Some(ID), Some(&name)),
+            Lookup::Av(ID, name.clone())
+        );
+        assert_eq!(
+            Lookup::plan(&db, None, Some(REF), Some(&V::EntityId(E))),
+            Lookup::Va(E, REF)
+        );
+        assert_eq!(
+            Lookup::plan(&db, None, None, Some(&V::EntityId(E))),
+            Lookup::V(E)
+        );
+        assert_eq!(Lookup::plan(&db, None, None, Some(&name)), Lookup::All);
+    }
+
+    #[test]
+    fn find() {
+        let db = people();
+        let (ada, grace, alan) = (string("Ada"), string("Grace"), string("Alan"));
+
+        // Joins on ?e and ?f, reading `:test/ref` backwards from a known value.
+        let q = query(
+            "[:find ?name ?referrer :where [?e :test/string ?name] [?f :test/ref ?e]
+                                           [?f :test/string ?referrer]]",
+        );
+        assert_eq!(
+            db.query(&q, vec![]),
+            Ok(QueryResult::Relation(vec![
+                vec![alan.clone(), ada.clone()],
+                vec![alan.clone(), grace.clone()],
+                vec![grace.clone(), ada.clone()],
+            ]))
+        );
+
+        let q = query("[:find [?name ...] :where [?e :test/longs 2] [?e :test/string ?name]]");
+        assert_eq!(
+            db.query(&q, vec![]),
+            Ok(QueryResult::Collection(vec![alan.clone(), grace.clone()]))
+        );
+
+        let q = query("[:find [?e ?longs] :where [?e :test/id \"ada\"] [?e :test/longs ?longs]]");
+        let tuple = vec![V::EntityId(E), V::I64(1)];
+        assert_eq!(db.query(&q, vec![]), Ok(QueryResult::Tuple(Some(tuple))));
+
+        let q = query("[:find ?name . :where [?e :test/id \"nobody\"] [?e :test/string ?name]]");
+        assert_eq!(db.query(&q, vec![]), Ok(QueryResult::Scalar(None)));
+
+        // The same variable in two positions of a clause.
+        let q = query("[:find ?e :where [?e :test/ref ?e]]");
+        assert_eq!(db.query(&q, vec![]), Ok(QueryResult::Relation(vec![])));
+
+        let q = query("[:find ?a . :where [?e ?a \"Ada\"]]");
+        assert_eq!(
+            db.query(&q, vec![]),
+            Ok(QueryResult::Scalar(Some(V::EntityId(STRING))))
+        );
+    }
+
+    #[test]
+    fn inputs() {
+        let db = people();
+        let (ada, grace, alan) = (string("Ada"), string("Grace"), string("Alan"));
+
+        let q = query("[:find ?name . :in $ ?id :where [?e :test/id ?id] [?e :test/string ?name]]");
+        assert_eq!(
+            db.query(&q, vec![Arg::Scalar(string("grace"))]),
+            Ok(QueryResult::Scalar(Some(grace.clone())))
+        );
+
+        let q = query(
+            "[:find [?name ...] :in [?id ...] :where [?e :test/id ?id] [?e :test/string ?name]]",
+        );
+        let ids = Arg::Collection(vec![string("ada"), string("alan"), string("nobody")]);
+        assert_eq!(
+            db.query(&q, vec![ids]),
+            Ok(QueryResult::Collection(vec![ada.clone(), alan]))
+        );
+
+        let q = query(
+            "[:find ?e . :in $ [?id ?name] :where [?e :test/id ?id] [?e :test/string ?name]]",
+        );
+        let tuple = |id, name| Arg::Tuple(vec![string(id), string(name)]);
+        let ada_id = Ok(QueryResult::Scalar(Some(V::EntityId(E))));
+        assert_eq!(db.query(&q, vec![tuple("ada", "Ada")]), ada_id);
+        assert_eq!(
+            db.query(&q, vec![tuple("ada", "Grace")]),
+            Ok(QueryResult::Scalar(None))
+        );
+
+        // Entities and attributes may be given as idents.
+        let q = query("[:find ?v . :in [[?a ?v]] :where [?e :test/id \"ada\"] [?e ?a ?v]]");
+        let relation = Arg::Relation(vec![
+            vec![keyword(":test/string"), ada.clone()],
+            vec![keyword(":test/string"), grace],
+            vec![keyword(":test/unknown"), ada.clone()],
+        ]);
+        assert_eq!(
+            db.query(&q, vec![relation]),
+            Ok(QueryResult::Scalar(Some(ada)))
+        );
+
+        assert!(matches!(db.query(&q, vec![]), Err(Error::Query(_))));
+        assert!(matches!(
+            db.query(&q, vec![Arg::Scalar(V::I64(1))]),
+            Err(Error::Query(_))
+        ));
+        let arity = Arg::Relation(vec![vec![V::I64(1)]]);
+        assert!(matches!(db.query(&q, vec![arity]), Err(Error::Query(_))));
+        let extra = vec![Arg::Relation(vec![]), Arg::Scalar(V::I64(1))];
+        assert!(matches!(db.query(&q, extra), Err(Error::Query(_))));
+        let q = query("[:find ?x :where [?e :test/id]]");
+        assert!(matches!(db.query(&q, vec![]), Err(Error::Query(_))));
+    }
+
+    #[test]
+    fn history_and_as_of() {
+        let db = db();
+        let report = db.transact(vec![TxOp::add(E, LONGS, V::I64(1))]).unwrap();
+        let t = report.db_after.basis_t();
+        let first = partition::tx_entity(t);
+        let report = report
+            .db_after
+            .transact(vec![
+                TxOp::retract(E, LONGS, V::I64(1)),
+                TxOp::add(E, LONGS, V::I64(2)),
+            ])
+            .unwrap();
+        let second = partition::tx_entity(report.db_after.basis_t());
+        let db = report.db_after;
+
+        let q = query("[:find [?v ...] :in $ ?e :where [?e :test/longs ?v]]");
+        let e = || vec![Arg::Scalar(V::EntityId(E))];
+        assert_eq!(
+            db.query(&q, e()),
+            Ok(QueryResult::Collection(vec![V::I64(2)]))
+        );
+        let as_of = db.as_of(&Time::TransactionId(t));
+        assert_eq!(
+            as_of.query(&q, e()),
+            Ok(QueryResult::Collection(vec![V::I64(1)]))
+        );
+
+        let q = query("[:find ?v ?tx ?added :where [?e :test/longs ?v ?tx ?added]]");
+        assert_eq!(
+            db.history().query(&q, vec![]),
+            Ok(QueryResult::Relation(vec![
+                vec![V::I64(1), V::EntityId(first), V::Boolean(true)],
+                vec![V::I64(1), V::EntityId(second), V::Boolean(false)],
+                vec![V::I64(2), V::EntityId(second), V::Boolean(true)],
+            ]))
+        );
+        let q = query("[:find ?v . :in $ ?tx :where [_ :test/longs ?v ?tx false]]");
+        let tx = vec![Arg::Scalar(V::EntityId(second))];
+        assert_eq!(
+            db.history().query(&q, tx),
+            Ok(QueryResult::Scalar(Some(V::I64(1))))
+        );
+        let by_t = vec![Arg::Scalar(V::I64(t + 1))];
+        assert_eq!(
+            db.history().query(&q, by_t),
+            Ok(QueryResult::Scalar(Some(V::I64(1))))
+        );
+        let q = query(&format!(
+            "[:find ?v . :where [_ :test/longs ?v {} true]]",
+            t
+        ));
+        assert_eq!(db.query(&q, vec![]), Ok(QueryResult::Scalar(None)));
+        assert_eq!(
+            as_of.query(&q, vec![]),
+            Ok(QueryResult::Scalar(Some(V::I64(1))))
+        );
+    }
+}
@@ -0,0 +1,374 @@
+use std::time::SystemTime;
+
+use crate::{
+    datom::Datom,
+    partition::{self, PART_DB, PART_TX, PART_USER},
+    AttributeId, EntityId, Key, V,
+};
+
+// The attributes every database starts with. They are installed at t = 0 by
+// `DatabaseSnapshot::new` and describe every other attribute, themselves included.
+pub const DB_IDENT: AttributeId = 10;
+pub const DB_VALUE_TYPE: AttributeId = 11;
+pub const DB_CARDINALITY: AttributeId = 12;
+pub const DB_UNIQUE: AttributeId = 13;
+pub const DB_IS_COMPONENT: AttributeId = 14;
+pub const DB_INDEX: AttributeId = 15;
+pub const DB_DOC: AttributeId = 16;
+// Marks an entity as a transaction function. The function itself is a Rust
+// closure, registered with the snapshot by `DatabaseSnapshot::install_fn`.
+pub const DB_FN: AttributeId = 17;
+// When a transaction was applied, asserted on the transaction's entity.
+pub const DB_TX_INSTANT: AttributeId = 18;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValueType {
+    String,
+    Long,
+    Ref,
+    Uuid,
+    Keyword,
+    Boolean,
+    Instant,
+}
+
+impl ValueType {
+    pub fn ident(&self) -> &'static str {
+        match self {
+            ValueType::String => ":db.type/string",
+            ValueType::Long => ":db.type/long",
+            ValueType::Ref => ":db.type/ref",
+            ValueType::Uuid => ":db.type/uuid",
+            ValueType::Keyword => ":db.type/keyword",
+            ValueType::Boolean => ":db.type/boolean",
+            ValueType::Instant => ":db.type/instant",
+        }
+    }
+
+    pub fn from_ident(ident: &str) -> Option<ValueType> {
+        match ident {
+            ":db.type/string" => Some(ValueType::String),
+            ":db.type/long" => Some(ValueType::Long),
+            ":db.type/ref" => Some(ValueType::Ref),
+            ":db.type/uuid" => Some(ValueType::Uuid),
+            ":db.type/keyword" => Some(ValueType::Keyword),
+            ":db.type/boolean" => Some(ValueType::Boolean),
+            ":db.type/instant" => Some(ValueType::Instant),
+            _ => None,
+        }
+    }
+
+    // Whether `v` is a value of this type.
+    pub fn admits(&self, v: &V) -> bool {
+        matches!(
+            (self, v),
+            (ValueType::String, V::String(_))
+                | (ValueType::Long, V::I64(_))
+                | (ValueType::Ref, V::EntityId(_))
+                | (ValueType::Uuid, V::Uuid(_))
+                | (ValueType::Keyword, V::Key(_))
+                | (ValueType::Boolean, V::Boolean(_))
+                | (ValueType::Instant, V::Instant(_))
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cardinality {
+    One,
+    Many,
+}
+
+impl Cardinality {
+    pub fn ident(&self) -> &'static str {
+        match self {
+            Cardinality::One => ":db.cardinality/one",
+            Cardinality::Many => ":db.cardinality/many",
+        }
+    }
+
+    pub fn from_ident(ident: &str) -> Option<Cardinality> {
+        match ident {
+            ":db.cardinality/one" => Some(Cardinality::One),
+            ":db.cardinality/many" => Some(Cardinality::Many),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Unique {
+    Identity,
+    Value,
+}
+
+impl Unique {
+    pub fn ident(&self) -> &'static str {
+        match self {
+            Unique::Identity => ":db.unique/identity",
+            Unique::Value => ":db.unique/value",
+        }
+    }
+
+    pub fn from_ident(ident: &str) -> Option<Unique> {
+        match ident {
+            ":db.unique/identity" => Some(Unique::Identity),
+            ":db.unique/value" => Some(Unique::Value),
+            _ => None,
+        }
+    }
+}
+
+// An attribute is an entity carrying at least `:db/ident`, `:db/valueType`
+// and `:db/cardinality`. This is the cached, typed view of that entity.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Attribute {
+    pub id: AttributeId,
+    pub ident: Key,
+    pub value_type: ValueType,
+    pub cardinality: Cardinality,
+    pub unique: Option<Unique>,
+    pub is_component: bool,
+    pub index: bool,
+}
+
+impl Attribute {
+    // Builds the attribute described by the current datoms of entity `id`, if
+    // they describe one.
+    pub(crate) fn from_datoms<'a>(
+        id: EntityId,
+        datoms: impl Iterator<Item = &'a Datom>,
+    ) -> Option<Attribute> {
+        let mut ident = None;
+        let mut value_type = None;
+        let mut cardinality = None;
+        let mut unique = None;
+        let mut is_component = false;
+        let mut index = false;
+        for datom in datoms {
+            match (datom.a, &datom.v) {
+                (DB_IDENT, V::Key(key)) => ident = Some(key.clone()),
+                (DB_VALUE_TYPE, V::Key(key)) => value_type = ValueType::from_ident(key),
+                (DB_CARDINALITY, V::Key(key)) => cardinality = Cardinality::from_ident(key),
+                (DB_UNIQUE, V::Key(key)) => unique = Unique::from_ident(key),
+                (DB_IS_COMPONENT, V::Boolean(b)) => is_component = *b,
+                (DB_INDEX, V::Boolean(b)) => index = *b,
+                _ => {}
+            }
+        }
+        Some(Attribute {
+            id,
+            ident: ident?,
+            value_type: value_type?,
+            cardinality: cardinality?,
+            unique,
+            is_component,
+            index,
+        })
+    }
+
+    // Whether the attribute's datoms are maintained in the AVET index.
+    pub fn indexed(&self) -> bool {
+        self.index || self.unique.is_some()
+    }
+}
+
+// Whether a datom on attribute `a` can change the schema.
+pub(crate) fn is_schema_attribute(a: AttributeId) -> bool {
+    (DB_IDENT..=DB_INDEX).contains(&a)
+}
+
+// The datoms describing the bootstrap partitions and attributes.
+pub(crate) fn bootstrap() -> Vec<Datom> {
+    let attributes = [
+        (
+            DB_IDENT,
+            ":db/ident",
+            ValueType::Keyword,
+            Some(Unique::Identity),
+            false,
+        ),
+        (
+            DB_VALUE_TYPE,
+            ":db/valueType",
+            ValueType::Keyword,
+            None,
+            false,
+        ),
+        (
+            DB_CARDINALITY,
+            ":db/cardinality",
+            ValueType::Keyword,
+            None,
+            false,
+        ),
+        (DB_UNIQUE, ":db/unique", ValueType::Keyword, None, false),
+        (
+            DB_IS_COMPONENT,
+            ":db/isComponent",
+            ValueType::Boolean,
+            None,
+            false,
+        ),
+        (DB_INDEX, ":db/index", ValueType::Boolean, None, false),
+        (DB_DOC, ":db/doc", ValueType::String, None, false),
+        (DB_FN, ":db/fn", ValueType::Boolean, None, false),
+        (
+            DB_TX_INSTANT,
+            ":db/txInstant",
+            ValueType::Instant,
+            None,
+            true,
+        ),
+    ];
+    let keyword = |ident: &str| V::Key(Key(ident.to_string()));
+    let mut datoms = vec![];
+    for (id, ident) in [
+        (PART_DB, ":db.part/db"),
+        (PART_TX, ":db.part/tx"),
+        (PART_USER, ":db.part/user"),
+    ] {
+        datoms.push(Datom::new(id, DB_IDENT, keyword(ident), 0, true));
+    }
+    for (id, ident, value_type, unique, index) in attributes {
+        datoms.push(Datom::new(id, DB_IDENT, keyword(ident), 0, true));
+        datoms.push(Datom::new(
+            id,
+            DB_VALUE_TYPE,
+            keyword(value_type.ident()),
+            0,
+            true,
+        ));
+        datoms.push(Datom::new(
+            id,
+            DB_CARDINALITY,
+            keyword(Cardinality::One.ident()),
+            0,
+            true,
+        ));
+        if let Some(unique) = unique {
+            datoms.push(Datom::new(id, DB_UNIQUE, keyword(unique.ident()), 0, true));
+        }
+        if index {
+            datoms.push(Datom::new(id, DB_INDEX, V::Boolean(true), 0, true));
+        }
+    }
+    // the bootstrap transaction itself, dated at the epoch so that every
+    // instant resolves to a transaction.
+    let instant = V::Instant(SystemTime::UNIX_EPOCH);
+    datoms.push(Datom::new(
+        partition::tx_entity(0),
+        DB_TX_INSTANT,
+        instant,
+        0,
+        true,
+    ));
+    datoms
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use crate::{database_snapshot::DatabaseSnapshot, schema::*, transaction::TxOp};
+
+    pub(crate) const LONGS: AttributeId = 100;
+    pub(crate) const STRING: AttributeId = 101;
+    pub(crate) const ID: AttributeId = 102;
+    pub(crate) const EMAIL: AttributeId = 103;
+    pub(crate) const REF: AttributeId = 104;
+    pub(crate) const COMPONENT: AttributeId = 105;
+    // An entity of `:db.part/user` for tests to say things about.
+    pub(crate) const E: EntityId = crate::partition::entity_id(PART_USER, 1);
+
+    fn keyword(ident: &str) -> V {
+        V::Key(Key::from(ident))
+    }
+
+    // A database with `:test/longs` (indexed, cardinality many), `:test/string`
+    // (cardinality one), `:test/id` (unique identity), `:test/email` (unique
+    // value), `:test/ref` (reference, cardinality many) and `:test/component`
+    // (component reference, cardinality many) installed in its first
+    // transaction.
+    pub(crate) fn db() -> DatabaseSnapshot {
+        DatabaseSnapshot::new()
+            .transact(vec![
+                TxOp::add(LONGS, DB_IDENT, keyword(":test/longs")),
+                TxOp::add(LONGS, DB_VALUE_TYPE, keyword(":db.type/long")),
+                TxOp::add(LONGS, DB_CARDINALITY, keyword(":db.cardinality/many")),
+                TxOp::add(LONGS, DB_INDEX, V::Boolean(true)),
+                TxOp::add(STRING, DB_IDENT, keyword(":test/string")),
+                TxOp::add(STRING, DB_VALUE_TYPE, keyword(":db.type/string")),
+                TxOp::add(STRING, DB_CARDINALITY, keyword(":db.cardinality/one")),
+                TxOp::add(ID, DB_IDENT, keyword(":test/id")),
+                TxOp::add(ID, DB_VALUE_TYPE, keyword(":db.type/string")),
+                TxOp::add(ID, DB_CARDINALITY, keyword(":db.cardinality/one")),
+                TxOp::add(ID, DB_UNIQUE, keyword(":db.unique/identity")),
+                TxOp::add(EMAIL, DB_IDENT, keyword(":test/email")),
+                TxOp::add(EMAIL, DB_VALUE_TYPE, keyword(":db.type/string")),
+                TxOp::add(EMAIL, DB_CARDINALITY, keyword(":db.cardinality/one")),
+                TxOp::add(EMAIL, DB_UNIQUE, keyword(":db.unique/value")),
+                TxOp::add(REF, DB_IDENT, keyword(":test/ref")),
+                TxOp::add(REF, DB_VALUE_TYPE, keyword(":db.type/ref")),
+                TxOp::add(REF, DB_CARDINALITY, keyword(":db.cardinality/many")),
+                TxOp::add(COMPONENT, DB_IDENT, keyword(":test/component")),
+                TxOp::add(COMPONENT, DB_VALUE_TYPE, keyword(":db.type/ref")),
+                TxOp::add(COMPONENT, DB_CARDINALITY, keyword(":db.cardinality/many")),
+                TxOp::add(COMPONENT, DB_IS_COMPONENT, V::Boolean(true)),
+            ])
+            .unwrap()
+            .db_after
+    }
+
+    #[test]
+    fn bootstrap() {
+        let db = DatabaseSnapshot::new();
+        let ident = db.attribute(Key::from(":db/ident")).unwrap();
+        assert_eq!(ident.id, DB_IDENT);
+        assert_eq!(ident.value_type, ValueType::Keyword);
+        assert_eq!(ident.unique, Some(Unique::Identity));
+        assert_eq!(db.select_av(DB_IDENT, &keyword(":db/doc")).count(), 1);
+        assert_eq!(
+            db.select_av(DB_VALUE_TYPE, &keyword(":db.type/keyword"))
+                .count(),
+            0
+        );
+    }
+
+    #[test]
+    fn install_attributes() {
+        let db = db();
+        let longs = db.attribute(LONGS).unwrap();
+        assert_eq!(longs.ident, Key::from(":test/longs"));
+        assert_eq!(longs.cardinality, Cardinality::Many);
+        assert!(longs.indexed());
+        let string = db.attribute(Key::from(":test/string")).unwrap();
+        assert_eq!(string.id, STRING);
+        assert!(!string.indexed());
+
+        // an attribute that becomes indexed has its existing datoms backfilled into AVET
+        let db = db
+            .transact(vec![TxOp::add(E, STRING, V::String("a".to_string()))])
+            .unwrap()
+            .db_after;
+        assert_eq!(db.select_av(STRING, &V::String("a".to_string())).count(), 0);
+        let db = db
+            .transact(vec![TxOp::add(
+                STRING,
+                DB_UNIQUE,
+                keyword(":db.unique/value"),
+            )])
+            .unwrap()
+            .db_after;
+        assert_eq!(db.select_av(STRING, &V::String("a".to_string())).count(), 1);
+
+        let db = db
+            .transact(vec![TxOp::retract(
+                STRING,
+                DB_IDENT,
+                keyword(":test/string"),
+            )])
+            .unwrap()
+            .db_after;
+        assert!(db.attribute(Key::from(":test/string")).is_none());
+        assert!(db.attribute(STRING).is_none());
+    }
+}
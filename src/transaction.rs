@@ -0,0 +1,1140 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use crate::{
+    database_snapshot::{DatabaseSnapshot, Identity},
+    datom::Datom,
+    error::{Error, Result},
+    partition,
+    schema::{self, Cardinality, Unique, ValueType},
+    AttributeId, EntityId, V,
+};
+
+// A single operation in a transaction's tx-data, mirroring
+// `[:db/add e a v]`, `[:db/retract e a v]`, `[:db/retractEntity e]`,
+// `[:db/cas e a old new]` and calls of installed transaction functions.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TxOp {
+    Add(EntityRef, Identity, V),
+    Retract(EntityRef, Identity, V),
+    // Retracts every attribute of the entity and every reference to it, and
+    // likewise for its components, recursively.
+    RetractEntity(Identity),
+    // Asserts the new value of a cardinality-one attribute, provided its
+    // current value is the old one (None meaning that it has none), and
+    // otherwise aborts the transaction.
+    Cas(Identity, Identity, Option<V>, V),
+    // Calls the transaction function named by the identity with the
+    // arguments, and applies the tx ops it returns in place of this one.
+    Call(Identity, Vec<V>),
+}
+
+// A transaction function. It is called with the database the transaction is
+// applied to and the call's arguments, and returns the tx ops to apply, which
+// may include further calls.
+pub type TxFn = Arc<dyn Fn(&DatabaseSnapshot, &[V]) -> Result<Vec<TxOp>> + Send + Sync>;
+
+impl TxOp {
+    pub fn add(e: impl Into<EntityRef>, a: impl Into<Identity>, v: V) -> TxOp {
+        TxOp::Add(e.into(), a.into(), v)
+    }
+
+    pub fn retract(e: impl Into<EntityRef>, a: impl Into<Identity>, v: V) -> TxOp {
+        TxOp::Retract(e.into(), a.into(), v)
+    }
+
+    pub fn retract_entity(e: impl Into<Identity>) -> TxOp {
+        TxOp::RetractEntity(e.into())
+    }
+
+    pub fn cas(e: impl Into<Identity>, a: impl Into<Identity>, old: Option<V>, new: V) -> TxOp {
+        TxOp::Cas(e.into(), a.into(), old, new)
+    }
+
+    pub fn call(f: impl Into<Identity>, args: Vec<V>) -> TxOp {
+        TxOp::Call(f.into(), args)
+    }
+}
+
+// The entity a tx op applies to: either an existing entity or a tempid
+// standing for one the transaction resolves. Negative entity ids are tempids
+// as well. In the value position of a reference attribute, a `V::String` or
+// negative `V::EntityId` is likewise read as a tempid.
+//
+// New entities are allocated in `:db.part/user`, except for tempids that
+// define an attribute, which go in `:db.part/db`, and `TempIdIn`, which names
+// the partition explicitly (any but `:db.part/tx`). `Tx` is the entity of the
+// transaction itself, for attaching metadata to it.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum EntityRef {
+    Id(EntityId),
+    TempId(String),
+    TempIdIn(EntityId, String),
+    Tx,
+}
+
+impl EntityRef {
+    // The tempid this stands for, as it's keyed in `TxReport::tempids`.
+    fn tempid(&self) -> Option<String> {
+        match self {
+            EntityRef::Id(e) if *e < 0 => Some(e.to_string()),
+            EntityRef::Id(_) | EntityRef::Tx => None,
+            EntityRef::TempId(tempid) | EntityRef::TempIdIn(_, tempid) => Some(tempid.clone()),
+        }
+    }
+}
+
+impl From<EntityId> for EntityRef {
+    fn from(e: EntityId) -> EntityRef {
+        EntityRef::Id(e)
+    }
+}
+
+impl From<&str> for EntityRef {
+    fn from(tempid: &str) -> EntityRef {
+        EntityRef::TempId(tempid.to_string())
+    }
+}
+
+// A tx op once it has been expanded into an assertion or retraction, with its
+// attribute resolved to an id.
+enum Expanded {
+    Add(EntityRef, AttributeId, V),
+    Retract(EntityRef, AttributeId, V),
+}
+
+// A tx op once its tempids have been resolved to entity ids.
+enum Resolved {
+    Add(EntityId, AttributeId, V),
+    Retract(EntityId, AttributeId, V),
+}
+
+pub struct TxReport {
+    pub db_before: DatabaseSnapshot,
+    pub db_after: DatabaseSnapshot,
+    // The assertions and retractions made by the transaction, all stamped with its `t`.
+    pub tx_data: Vec<Datom>,
+    pub tempids: HashMap<String, EntityId>,
+}
+
+pub(crate) fn transact(db: &DatabaseSnapshot, ops: Vec<TxOp>) -> Result<TxReport> {
+    if db.as_of_t().is_some() {
+        return Err(Error::AsOf);
+    }
+    let t = db.basis_t() + 1;
+    let tx = partition::tx_entity(t);
+    let ops = expand(db, ops)?;
+    let (mut ops, tempids) = resolve_tempids(db, ops, tx)?;
+
+    // Every transaction records when it was applied on its entity, and only
+    // there. The time may be given explicitly, but like the wall clock reading
+    // used otherwise, it may not go back past the previous transaction's.
+    let previous = db
+        .select_ea(partition::tx_entity(db.basis_t()), schema::DB_TX_INSTANT)
+        .find_map(|datom| match datom.v {
+            V::Instant(instant) => Some(instant),
+            _ => None,
+        })
+        .unwrap_or(SystemTime::UNIX_EPOCH);
+    let misplaced = ops.iter().find_map(|op| match op {
+        Resolved::Add(e, schema::DB_TX_INSTANT, _)
+        | Resolved::Retract(e, schema::DB_TX_INSTANT, _)
+            if *e != tx =>
+        {
+            Some(*e)
+        }
+        _ => None,
+    });
+    if let Some(e) = misplaced {
+        return Err(Error::TxInstantEntity(e));
+    }
+    let explicit = ops.iter().find_map(|op| match op {
+        Resolved::Add(e, schema::DB_TX_INSTANT, V::Instant(instant)) if *e == tx => Some(*instant),
+        _ => None,
+    });
+    match explicit {
+        Some(instant) if instant < previous => {
+            return Err(Error::TxInstant { instant, previous });
+        }
+        Some(_) => {}
+        None => {
+            let instant = SystemTime::now().max(previous);
+            ops.push(Resolved::Add(
+                tx,
+                schema::DB_TX_INSTANT,
+                V::Instant(instant),
+            ));
+        }
+    }
+
+    // The facts are kept in order of first appearance, with sets beside them
+    // for membership checks.
+    let mut assertions: Vec<(EntityId, AttributeId, V)> = vec![];
+    let mut retractions: Vec<(EntityId, AttributeId, V)> = vec![];
+    let mut asserted: HashSet<(EntityId, AttributeId, V)> = HashSet::new();
+    let mut retracted: HashSet<(EntityId, AttributeId, V)> = HashSet::new();
+    for op in ops {
+        match op {
+            Resolved::Add(e, a, v) => {
+                let fact = (e, a, v);
+                if retracted.contains(&fact) {
+                    let (e, a, v) = fact;
+                    return Err(Error::Conflict { e, a, v });
+                }
+                if asserted.insert(fact.clone()) {
+                    assertions.push(fact);
+                }
+            }
+            Resolved::Retract(e, a, v) => {
+                let fact = (e, a, v);
+                if asserted.contains(&fact) {
+                    let (e, a, v) = fact;
+                    return Err(Error::Conflict { e, a, v });
+                }
+                if retracted.insert(fact.clone()) {
+                    retractions.push(fact);
+                }
+            }
+        }
+    }
+
+    for (e, a, v) in &retractions {
+        validate(db, Datom::new(*e, *a, v.clone(), t, false))?;
+    }
+    for (e, a, v) in &assertions {
+        validate(db, Datom::new(*e, *a, v.clone(), t, true))?;
+    }
+
+    // Asserting a value of a cardinality-one attribute implicitly retracts
+    // whatever value the entity currently has for it.
+    let mut implied = vec![];
+    let mut values: HashMap<(EntityId, AttributeId), &V> = HashMap::new();
+    for (e, a, v) in &assertions {
+        if db.attribute(*a).map(|attribute| attribute.cardinality) != Some(Cardinality::One) {
+            continue;
+        }
+        if let Some(other) = values.insert((*e, *a), v) {
+            return Err(Error::Cardinality {
+                e: *e,
+                a: *a,
+                values: (other.clone(), v.clone()),
+            });
+        }
+        for datom in db.select_ea(*e, *a) {
+            let fact = (*e, *a, datom.v.clone());
+            if datom.v != *v && !retracted.contains(&fact) {
+                implied.push(fact);
+            }
+        }
+    }
+    retracted.extend(implied.iter().cloned());
+    retractions.extend(implied);
+
+    // A value of a unique attribute may only be held by one entity at a time.
+    let is_unique = |a: AttributeId| db.attribute(a).is_some_and(|attr| attr.unique.is_some());
+    let mut holders: HashMap<(AttributeId, &V), Vec<EntityId>> = HashMap::new();
+    for (e, a, v) in assertions.iter().filter(|(_, a, _)| is_unique(*a)) {
+        holders.entry((*a, v)).or_default().push(*e);
+    }
+    for (e, a, v) in assertions.iter().filter(|(_, a, _)| is_unique(*a)) {
+        let existing = db
+            .select_av(*a, v)
+            .map(|datom| datom.e)
+            .filter(|existing| !retracted.contains(&(*existing, *a, v.clone())))
+            .chain(holders[&(*a, v)].iter().copied())
+            .find(|existing| existing != e);
+        if let Some(existing) = existing {
+            return Err(Error::Unique {
+                datom: Datom::new(*e, *a, v.clone(), t, true),
+                existing,
+            });
+        }
+    }
+
+    let mut db_after = db.clone();
+    let mut tx_data = vec![];
+    for (e, a, v) in retractions {
+        // retracting a fact that does not currently hold is a no-op.
+        if !holds(db, e, a, &v) {
+            continue;
+        }
+        let datom = Datom::new(e, a, v, t, false);
+        db_after = db_after.insert(datom.clone());
+        tx_data.push(datom);
+    }
+    for (e, a, v) in assertions {
+        // asserting a fact that already holds is redundant and produces no datom.
+        if holds(db, e, a, &v) {
+            continue;
+        }
+        let datom = Datom::new(e, a, v, t, true);
+        db_after = db_after.insert(datom.clone());
+        tx_data.push(datom);
+    }
+
+    Ok(TxReport {
+        db_before: db.clone(),
+        db_after: db_after.refresh_schema(&tx_data),
+        tx_data,
+        tempids,
+    })
+}
+
+// How deeply transaction function calls may nest: the ops returned by a call
+// made by the ops returned by a call, and so on. Deeper nesting is taken to be
+// a function calling itself without end.
+pub const MAX_CALL_DEPTH: usize = 64;
+
+// Rewrites the ops that aren't plain assertions and retractions into the
+// assertions and retractions they stand for. The ops a function call returns
+// are expanded in turn, in place of the call.
+fn expand(db: &DatabaseSnapshot, ops: Vec<TxOp>) -> Result<Vec<Expanded>> {
+    let mut expanded = vec![];
+    // Each op is paired with the number of calls it was returned through.
+    let mut pending: Vec<(TxOp, usize)> = ops.into_iter().rev().map(|op| (op, 0)).collect();
+    while let Some((op, depth)) = pending.pop() {
+        match op {
+            TxOp::Add(e, a, v) => expanded.push(Expanded::Add(e, db.ent_id(a)?, v)),
+            TxOp::Retract(e, a, v) => expanded.push(Expanded::Retract(e, db.ent_id(a)?, v)),
+            TxOp::RetractEntity(e) => {
+                let mut entities = vec![db.ent_id(e)?];
+                let mut retracted = HashSet::new();
+                while let Some(e) = entities.pop() {
+                    if !retracted.insert(e) {
+                        continue;
+                    }
+                    for datom in db.select_e(e) {
+                        let component = db
+                            .attribute(datom.a)
+                            .is_some_and(|attribute| attribute.is_component);
+                        if let (true, Some(component)) = (component, datom.v.entity_id()) {
+                            entities.push(component);
+                        }
+                        expanded.push(Expanded::Retract(e.into(), datom.a, datom.v.clone()));
+                    }
+                    for datom in db.select_v(e) {
+                        let v = datom.v.clone();
+                        expanded.push(Expanded::Retract(datom.e.into(), datom.a, v));
+                    }
+                }
+            }
+            TxOp::Cas(e, a, old, new) => {
+                let (e, a) = (db.ent_id(e)?, db.ent_id(a)?);
+                let cardinality = db.attribute(a).map(|attribute| attribute.cardinality);
+                if cardinality != Some(Cardinality::One) {
+                    return Err(Error::NotCardinalityOne(a));
+                }
+                let current = db.select_ea(e, a).next().map(|datom| datom.v.clone());
+                if current != old {
+                    return Err(Error::Cas {
+                        e,
+                        a,
+                        expected: old,
+                        current,
+                    });
+                }
+                expanded.push(Expanded::Add(e.into(), a, new));
+            }
+            TxOp::Call(f, _) if depth == MAX_CALL_DEPTH => return Err(Error::CallDepth(f)),
+            TxOp::Call(f, args) => {
+                let function = db.function(db.ent_id(f.clone())?);
+                let function = function.ok_or(Error::UnknownFunction(f))?;
+                let ops = function(db, &args)?.into_iter().rev();
+                pending.extend(ops.map(|op| (op, depth + 1)));
+            }
+        }
+    }
+    Ok(expanded)
+}
+
+// Tempids stand for entities that the transaction creates. A tempid asserting
+// a value of a `:db.unique/identity` attribute that an entity already holds
+// resolves to that entity instead (an upsert). The rest are allocated new
+// entity ids in order of first appearance.
+fn resolve_tempids(
+    db: &DatabaseSnapshot,
+    ops: Vec<Expanded>,
+    tx: EntityId,
+) -> Result<(Vec<Resolved>, HashMap<String, EntityId>)> {
+    let value_tempid = |a: AttributeId, v: &V| {
+        let is_ref = db
+            .attribute(a)
+            .is_some_and(|attribute| attribute.value_type == ValueType::Ref);
+        match v {
+            V::EntityId(e) if is_ref && *e < 0 => Some(e.to_string()),
+            V::String(tempid) if is_ref => Some(tempid.clone()),
+            _ => None,
+        }
+    };
+
+    let mut order: Vec<String> = vec![];
+    let mut seen: HashSet<String> = HashSet::new();
+    // Tempids that appear in an entity position, which all tempids must.
+    let mut entities: HashSet<String> = HashSet::new();
+    let mut partitions: HashMap<String, EntityId> = HashMap::new();
+    let mut tempids: HashMap<String, EntityId> = HashMap::new();
+    for op in &ops {
+        let (Expanded::Add(e, a, v) | Expanded::Retract(e, a, v)) = op;
+        let a = *a;
+        for tempid in [e.tempid(), value_tempid(a, v)].into_iter().flatten() {
+            if seen.insert(tempid.clone()) {
+                order.push(tempid);
+            }
+        }
+        if let Some(tempid) = e.tempid() {
+            entities.insert(tempid);
+        }
+        match (e, a) {
+            // Entities of `:db.part/tx` are only made by transacting.
+            (EntityRef::TempIdIn(partition, _), _)
+                if !partition::is_valid(*partition) || *partition == partition::PART_TX =>
+            {
+                return Err(Error::InvalidPartition(*partition));
+            }
+            (EntityRef::TempIdIn(partition, tempid), _) => {
+                partitions.insert(tempid.clone(), *partition);
+            }
+            (e, schema::DB_VALUE_TYPE) => {
+                if let Some(tempid) = e.tempid() {
+                    partitions.entry(tempid).or_insert(partition::PART_DB);
+                }
+            }
+            _ => {}
+        }
+        let upsert = matches!(op, Expanded::Add(..))
+            && db
+                .attribute(a)
+                .is_some_and(|attribute| attribute.unique == Some(Unique::Identity));
+        let (Some(tempid), true) = (e.tempid(), upsert) else {
+            continue;
+        };
+        if let Some(existing) = db.select_av(a, v).next().map(|datom| datom.e) {
+            match tempids.insert(tempid.clone(), existing) {
+                Some(other) if other != existing => {
+                    return Err(Error::TempidConflict {
+                        tempid,
+                        entities: (other, existing),
+                    })
+                }
+                _ => {}
+            }
+        }
+    }
+
+    if let Some(tempid) = order.iter().find(|tempid| !entities.contains(*tempid)) {
+        return Err(Error::TempidNotAnEntity(tempid.clone()));
+    }
+
+    let mut next: HashMap<EntityId, EntityId> = HashMap::new();
+    for tempid in order {
+        if tempids.contains_key(&tempid) {
+            continue;
+        }
+        let partition = partitions
+            .get(&tempid)
+            .copied()
+            .unwrap_or(partition::PART_USER);
+        let e = next
+            .entry(partition)
+            .or_insert_with(|| db.next_entity_id(partition));
+        tempids.insert(tempid, *e);
+        *e += 1;
+    }
+
+    let resolve = |e: EntityRef| match e.tempid() {
+        Some(tempid) => tempids[&tempid],
+        None => match e {
+            EntityRef::Id(e) => e,
+            EntityRef::Tx => tx,
+            _ => unreachable!("named tempids always have a key"),
+        },
+    };
+    let resolve_v = |a: AttributeId, v: V| match value_tempid(a, &v) {
+        Some(tempid) => V::EntityId(tempids[&tempid]),
+        None => v,
+    };
+    let ops = ops
+        .into_iter()
+        .map(|op| match op {
+            Expanded::Add(e, a, v) => Resolved::Add(resolve(e), a, resolve_v(a, v)),
+            Expanded::Retract(e, a, v) => Resolved::Retract(resolve(e), a, resolve_v(a, v)),
+        })
+        .collect();
+    Ok((ops, tempids))
+}
+
+// Checks that a datom names an installed attribute and, if it is an assertion,
+// that its value matches the attribute's `:db/valueType`.
+fn validate(db: &DatabaseSnapshot, datom: Datom) -> Result<()> {
+    match db.attribute(datom.a) {
+        None => Err(Error::UnknownAttribute { datom }),
+        Some(attribute) if datom.added && !attribute.value_type.admits(&datom.v) => {
+            Err(Error::InvalidValue {
+                expected: attribute.value_type,
+                datom,
+            })
+        }
+        Some(_) => Ok(()),
+    }
+}
+
+// Whether `[e a v]` is currently asserted in `db`.
+fn holds(db: &DatabaseSnapshot, e: EntityId, a: AttributeId, v: &V) -> bool {
+    db.select_eav(e, a, v).next().is_some()
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, SystemTime};
+
+    use crate::{
+        database_snapshot::{DatabaseSnapshot, Identity, LookupRef, Time},
+        datom::Datom,
+        error::Error,
+        partition,
+        schema::{
+            test::{db, COMPONENT, E, EMAIL, ID, LONGS, REF, STRING},
+            ValueType, DB_CARDINALITY, DB_FN, DB_IDENT, DB_TX_INSTANT, DB_VALUE_TYPE,
+        },
+        transaction::{EntityRef, TxOp, TxReport},
+        Key, V,
+    };
+
+    // The datoms of a transaction other than its `:db/txInstant`.
+    fn data(report: &TxReport) -> Vec<Datom> {
+        let data = report
+            .tx_data
+            .iter()
+            .filter(|datom| datom.a != DB_TX_INSTANT);
+        data.cloned().collect()
+    }
+
+    #[test]
+    fn transact_asserts_and_retracts() {
+        let db = db();
+
+        let report = db
+            .transact(vec![
+                TxOp::add(E, STRING, V::String("a".to_string())),
+                TxOp::add(E, LONGS, V::I64(7)),
+                TxOp::add(E, LONGS, V::I64(7)),
+            ])
+            .unwrap();
+        assert_eq!(data(&report).len(), 2);
+        assert!(report.tx_data.iter().all(|datom| datom.t == 2));
+        assert_eq!(report.db_before.select_e(E).count(), 0);
+        assert_eq!(report.db_after.basis_t(), 2);
+        assert_eq!(report.db_after.select_e(E).count(), 2);
+        assert_eq!(report.db_after.select_a(LONGS).count(), 1);
+
+        let report = report
+            .db_after
+            .transact(vec![
+                TxOp::retract(E, LONGS, V::I64(7)),
+                TxOp::retract(E, LONGS, V::I64(8)),
+            ])
+            .unwrap();
+        assert_eq!(
+            data(&report),
+            vec![Datom::new(E, LONGS, V::I64(7), 3, false)]
+        );
+        assert_eq!(report.db_after.basis_t(), 3);
+        assert_eq!(report.db_before.select_ea(E, LONGS).count(), 1);
+        assert_eq!(report.db_after.select_ea(E, LONGS).count(), 0);
+        assert_eq!(report.db_after.select_ae(LONGS, E).count(), 0);
+
+        let history = report.db_after.history();
+        let history: Vec<bool> = history.select_ea(E, LONGS).map(|d| d.added).collect();
+        assert_eq!(history, vec![true, false]);
+    }
+
+    #[test]
+    fn transact_rejects_conflicting_ops() {
+        let result = db().transact(vec![
+            TxOp::add(E, LONGS, V::I64(1)),
+            TxOp::retract(E, LONGS, V::I64(1)),
+        ]);
+        assert_eq!(
+            result.err(),
+            Some(Error::Conflict {
+                e: E,
+                a: LONGS,
+                v: V::I64(1)
+            })
+        );
+    }
+
+    #[test]
+    fn transact_validates_value_types() {
+        let db = db();
+        let result = db.transact(vec![
+            TxOp::add(E, STRING, V::String("a".to_string())),
+            TxOp::add(E, LONGS, V::String("7".to_string())),
+        ]);
+        assert_eq!(
+            result.err(),
+            Some(Error::InvalidValue {
+                datom: Datom::new(E, LONGS, V::String("7".to_string()), 2, true),
+                expected: ValueType::Long,
+            })
+        );
+
+        let result = db.transact(vec![TxOp::add(E, 999, V::I64(7))]);
+        assert_eq!(
+            result.err(),
+            Some(Error::UnknownAttribute {
+                datom: Datom::new(E, 999, V::I64(7), 2, true),
+            })
+        );
+    }
+
+    #[test]
+    fn cardinality_one_replaces_value() {
+        let db = db();
+        let db = db
+            .transact(vec![
+                TxOp::add(E, STRING, V::String("a".to_string())),
+                TxOp::add(E, LONGS, V::I64(1)),
+            ])
+            .unwrap()
+            .db_after;
+
+        let report = db
+            .transact(vec![
+                TxOp::add(E, STRING, V::String("b".to_string())),
+                TxOp::add(E, LONGS, V::I64(2)),
+            ])
+            .unwrap();
+        assert_eq!(
+            data(&report),
+            vec![
+                Datom::new(E, STRING, V::String("a".to_string()), 3, false),
+                Datom::new(E, STRING, V::String("b".to_string()), 3, true),
+                Datom::new(E, LONGS, V::I64(2), 3, true),
+            ]
+        );
+        let db = report.db_after;
+        let strings: Vec<_> = db
+            .select_ea(E, STRING)
+            .map(|datom| datom.v.clone())
+            .collect();
+        assert_eq!(strings, vec![V::String("b".to_string())]);
+        assert_eq!(db.select_ea(E, LONGS).count(), 2);
+
+        let result = db.transact(vec![
+            TxOp::add(E, STRING, V::String("c".to_string())),
+            TxOp::add(E, STRING, V::String("d".to_string())),
+        ]);
+        assert_eq!(
+            result.err(),
+            Some(Error::Cardinality {
+                e: E,
+                a: STRING,
+                values: (V::String("c".to_string()), V::String("d".to_string())),
+            })
+        );
+    }
+
+    #[test]
+    fn unique_identity_upserts() {
+        let report = db()
+            .transact(vec![
+                TxOp::add(-1, ID, V::String("ada".to_string())),
+                TxOp::add(-1, EMAIL, V::String("ada@example.com".to_string())),
+            ])
+            .unwrap();
+        let ada = report.tempids["-1"];
+        assert!(ada > EMAIL);
+
+        let report = report
+            .db_after
+            .transact(vec![
+                TxOp::add(-7, ID, V::String("ada".to_string())),
+                TxOp::add(-7, LONGS, V::I64(36)),
+            ])
+            .unwrap();
+        assert_eq!(report.tempids["-7"], ada);
+        assert_eq!(
+            data(&report),
+            vec![Datom::new(ada, LONGS, V::I64(36), 3, true)]
+        );
+
+        let db = report.db_after;
+        let result = db.transact(vec![
+            TxOp::add(-1, ID, V::String("grace".to_string())),
+            TxOp::add(-1, EMAIL, V::String("ada@example.com".to_string())),
+        ]);
+        assert!(matches!(
+            result.err(),
+            Some(Error::Unique { existing, .. }) if existing == ada
+        ));
+
+        let result = db.transact(vec![TxOp::add(E, ID, V::String("ada".to_string()))]);
+        assert!(matches!(
+            result.err(),
+            Some(Error::Unique { existing, .. }) if existing == ada
+        ));
+    }
+
+    #[test]
+    fn unique_values_may_move_between_entities() {
+        let other = partition::entity_id(partition::PART_USER, 2);
+        let email = |s: &str| V::String(s.to_string());
+        let db = db()
+            .transact(vec![TxOp::add(E, EMAIL, email("x"))])
+            .unwrap()
+            .db_after;
+        let report = db
+            .transact(vec![
+                TxOp::add(E, EMAIL, email("y")),
+                TxOp::add(other, EMAIL, email("x")),
+            ])
+            .unwrap();
+        let holders = |v| {
+            let datoms = report.db_after.select_av(EMAIL, &v);
+            datoms.map(|datom| datom.e).collect::<Vec<_>>()
+        };
+        assert_eq!(holders(email("x")), vec![other]);
+        assert_eq!(holders(email("y")), vec![E]);
+    }
+
+    #[test]
+    fn attributes_may_be_given_by_ident() {
+        let string = Key::from(":test/string");
+        let report = db()
+            .transact(vec![
+                TxOp::add(E, string.clone(), V::String("a".to_string())),
+                TxOp::add(E, Key::from(":test/longs"), V::I64(1)),
+            ])
+            .unwrap();
+        assert_eq!(
+            data(&report)[0],
+            Datom::new(E, STRING, V::String("a".to_string()), 2, true)
+        );
+        let db = report.db_after;
+        let cas = TxOp::cas(
+            E,
+            string.clone(),
+            Some(V::String("a".into())),
+            V::String("b".into()),
+        );
+        assert_eq!(
+            db.transact(vec![cas]).map(|report| data(&report).len()),
+            Ok(2)
+        );
+        let retract = TxOp::retract(E, string, V::String("a".to_string()));
+        assert_eq!(
+            db.transact(vec![retract]).map(|report| data(&report).len()),
+            Ok(1)
+        );
+
+        let unknown = Identity::Keyword(Key::from(":test/unknown"));
+        assert_eq!(
+            db.transact(vec![TxOp::add(E, unknown.clone(), V::I64(1))])
+                .err(),
+            Some(Error::NotFound(unknown))
+        );
+    }
+
+    #[test]
+    fn tempids_are_allocated_and_reported() {
+        let db = db();
+        let report = db
+            .transact(vec![
+                TxOp::add("ada", STRING, V::String("Ada".to_string())),
+                TxOp::add("ada", REF, V::String("grace".to_string())),
+                TxOp::add(-1, REF, V::EntityId(-1)),
+                TxOp::add("grace", STRING, V::String("Grace".to_string())),
+            ])
+            .unwrap();
+        let ada = report.tempids["ada"];
+        let grace = report.tempids["grace"];
+        let other = report.tempids["-1"];
+        assert_eq!(report.tempids.len(), 3);
+        assert_eq!((grace, other), (ada + 1, ada + 2));
+
+        let db = report.db_after;
+        let refs: Vec<_> = db
+            .select_ea(ada, REF)
+            .map(|datom| datom.v.clone())
+            .collect();
+        assert_eq!(refs, vec![V::EntityId(grace)]);
+        assert_eq!(db.select_eav(other, REF, &V::EntityId(other)).count(), 1);
+
+        // string values of non-reference attributes are left alone
+        let report = db
+            .transact(vec![TxOp::add(-1, STRING, V::String("ada".to_string()))])
+            .unwrap();
+        assert_eq!(report.tempids["-1"], other + 1);
+        assert_eq!(report.tx_data[0].v, V::String("ada".to_string()));
+
+        // a tempid has to stand for an entity that the transaction says something about
+        let result = db.transact(vec![TxOp::add(ada, REF, V::String("nobody".to_string()))]);
+        assert_eq!(
+            result.err(),
+            Some(Error::TempidNotAnEntity("nobody".to_string()))
+        );
+    }
+
+    #[test]
+    fn tempids_are_allocated_by_partition() {
+        let part = EntityRef::TempIdIn(partition::PART_DB, "part".to_string());
+        let report = db()
+            .transact(vec![
+                TxOp::add("attr", DB_IDENT, V::Key(Key::from(":test/other"))),
+                TxOp::add("attr", DB_VALUE_TYPE, V::Key(Key::from(":db.type/long"))),
+                TxOp::add(
+                    "attr",
+                    DB_CARDINALITY,
+                    V::Key(Key::from(":db.cardinality/one")),
+                ),
+                TxOp::add(part, DB_IDENT, V::Key(Key::from(":test.part/things"))),
+                TxOp::add("user", LONGS, V::I64(1)),
+            ])
+            .unwrap();
+        let attr = report.tempids["attr"];
+        let part = report.tempids["part"];
+        let user = report.tempids["user"];
+        assert_eq!(partition::partition(attr), partition::PART_DB);
+        assert_eq!((attr, part), (COMPONENT + 1, COMPONENT + 2));
+        assert_eq!(user, partition::entity_id(partition::PART_USER, 0));
+
+        let db = report.db_after;
+        let report = db
+            .transact(vec![
+                TxOp::add(EntityRef::TempIdIn(part, "a".to_string()), LONGS, V::I64(1)),
+                TxOp::add(EntityRef::TempIdIn(part, "b".to_string()), LONGS, V::I64(2)),
+                TxOp::add("c", LONGS, V::I64(3)),
+            ])
+            .unwrap();
+        assert_eq!(report.tempids["a"], partition::entity_id(part, 0));
+        assert_eq!(report.tempids["b"], partition::entity_id(part, 1));
+        assert_eq!(report.tempids["c"], user + 1);
+
+        let result = db.transact(vec![TxOp::add(
+            EntityRef::TempIdIn(-1, "a".to_string()),
+            LONGS,
+            V::I64(1),
+        )]);
+        assert_eq!(result.err(), Some(Error::InvalidPartition(-1)));
+
+        // a tempid in the transaction partition could take a transaction's entity id
+        let tx = EntityRef::TempIdIn(partition::PART_TX, "tx".to_string());
+        let result = db.transact(vec![TxOp::add(tx, LONGS, V::I64(1))]);
+        assert_eq!(
+            result.err(),
+            Some(Error::InvalidPartition(partition::PART_TX))
+        );
+    }
+
+    #[test]
+    fn retract_entity_cascades_to_components() {
+        let part = partition::entity_id(partition::PART_USER, 2);
+        let subpart = partition::entity_id(partition::PART_USER, 3);
+        let other = partition::entity_id(partition::PART_USER, 4);
+        let db = db()
+            .transact(vec![
+                TxOp::add(E, ID, V::String("ada".to_string())),
+                TxOp::add(E, COMPONENT, V::EntityId(part)),
+                TxOp::add(E, REF, V::EntityId(other)),
+                TxOp::add(part, STRING, V::String("part".to_string())),
+                TxOp::add(part, COMPONENT, V::EntityId(subpart)),
+                TxOp::add(subpart, LONGS, V::I64(1)),
+                TxOp::add(other, STRING, V::String("other".to_string())),
+            ])
+            .unwrap()
+            .db_after;
+
+        let ada = LookupRef::new(Key::from(":test/id"), V::String("ada".to_string()));
+        let report = db
+            .transact(vec![TxOp::retract_entity(ada.clone())])
+            .unwrap();
+        assert_eq!(data(&report).len(), 6);
+        assert!(data(&report).iter().all(|datom| !datom.added));
+        let db = report.db_after;
+        for e in [E, part, subpart] {
+            assert_eq!(db.select_e(e).count(), 0);
+        }
+        assert_eq!(db.select_e(other).count(), 1);
+
+        let missing = Identity::LookupRef(ada);
+        assert_eq!(
+            db.transact(vec![TxOp::RetractEntity(missing.clone())])
+                .err(),
+            Some(Error::NotFound(missing))
+        );
+    }
+
+    #[test]
+    fn retract_entity_retracts_references() {
+        let referrer = partition::entity_id(partition::PART_USER, 2);
+        let db = db()
+            .transact(vec![
+                TxOp::add(E, STRING, V::String("a".to_string())),
+                TxOp::add(referrer, REF, V::EntityId(E)),
+                TxOp::add(referrer, STRING, V::String("b".to_string())),
+            ])
+            .unwrap()
+            .db_after;
+
+        let report = db.transact(vec![TxOp::retract_entity(E)]).unwrap();
+        assert_eq!(data(&report).len(), 2);
+        let db = report.db_after;
+        assert_eq!(db.select_v(E).count(), 0);
+        assert_eq!(db.select_e(referrer).count(), 1);
+    }
+
+    #[test]
+    fn cas() {
+        let a = || V::String("a".to_string());
+        let b = || V::String("b".to_string());
+        let db = db()
+            .transact(vec![TxOp::cas(E, STRING, None, a())])
+            .unwrap()
+            .db_after;
+        let db = db
+            .transact(vec![TxOp::cas(E, STRING, Some(a()), b())])
+            .unwrap()
+            .db_after;
+        let values: Vec<V> = db
+            .select_ea(E, STRING)
+            .map(|datom| datom.v.clone())
+            .collect();
+        assert_eq!(values, vec![b()]);
+
+        assert_eq!(
+            db.transact(vec![TxOp::cas(E, STRING, Some(a()), b())])
+                .err(),
+            Some(Error::Cas {
+                e: E,
+                a: STRING,
+                expected: Some(a()),
+                current: Some(b())
+            })
+        );
+        assert_eq!(
+            db.transact(vec![TxOp::cas(E, LONGS, None, V::I64(1))])
+                .err(),
+            Some(Error::NotCardinalityOne(LONGS))
+        );
+    }
+
+    #[test]
+    fn transaction_functions() {
+        let db = db()
+            .install_fn(Key::from(":test/add-longs"), |db, args| {
+                let e = args[0].entity_id().unwrap();
+                let total = db.select_ea(e, LONGS).count() as i64;
+                Ok(args[1..]
+                    .iter()
+                    .enumerate()
+                    .map(|(i, _)| TxOp::add(e, LONGS, V::I64(total + i as i64)))
+                    .collect())
+            })
+            .unwrap()
+            .db_after;
+        let db = db
+            .install_fn(Key::from(":test/tag"), |_, args| {
+                let e = args[0].entity_id().unwrap();
+                Ok(vec![
+                    TxOp::add(e, STRING, args[1].clone()),
+                    TxOp::call(
+                        Key::from(":test/add-longs"),
+                        vec![V::EntityId(e), V::I64(0)],
+                    ),
+                ])
+            })
+            .unwrap()
+            .db_after;
+        let f = db.ent_id(Key::from(":test/tag")).unwrap();
+        assert_eq!(db.select_eav(f, DB_FN, &V::Boolean(true)).count(), 1);
+
+        let report = db
+            .transact(vec![
+                TxOp::add(E, LONGS, V::I64(10)),
+                TxOp::call(
+                    Key::from(":test/tag"),
+                    vec![V::EntityId(E), V::String("a".into())],
+                ),
+            ])
+            .unwrap();
+        assert_eq!(data(&report).len(), 3);
+        let db = report.db_after;
+        assert_eq!(
+            db.select_eav(E, STRING, &V::String("a".to_string()))
+                .count(),
+            1
+        );
+        assert_eq!(db.select_eav(E, LONGS, &V::I64(0)).count(), 1);
+
+        let args = vec![V::EntityId(E), V::Boolean(true), V::Boolean(true)];
+        let report = db
+            .transact(vec![TxOp::call(Key::from(":test/add-longs"), args)])
+            .unwrap();
+        let longs: Vec<V> = data(&report).iter().map(|datom| datom.v.clone()).collect();
+        assert_eq!(longs, vec![V::I64(2), V::I64(3)]);
+
+        let unknown = Identity::EntityId(E);
+        assert_eq!(
+            db.transact(vec![TxOp::Call(unknown.clone(), vec![])]).err(),
+            Some(Error::UnknownFunction(unknown))
+        );
+    }
+
+    #[test]
+    fn installed_functions_follow_time_and_retraction() {
+        let set = |s: &'static str| {
+            move |_: &DatabaseSnapshot, _: &[V]| Ok(vec![TxOp::add(E, STRING, V::String(s.into()))])
+        };
+        let call = || vec![TxOp::call(Key::from(":test/set"), vec![])];
+        let name = |report: TxReport| data(&report)[0].v.clone();
+        let before = db();
+        let first = before
+            .install_fn(Key::from(":test/set"), set("a"))
+            .unwrap()
+            .db_after;
+        let second = first
+            .install_fn(Key::from(":test/set"), set("b"))
+            .unwrap()
+            .db_after;
+        assert_eq!(
+            first.transact(call()).map(name),
+            Ok(V::String("a".to_string()))
+        );
+        assert_eq!(
+            second.transact(call()).map(name),
+            Ok(V::String("b".to_string()))
+        );
+
+        // As-of views see the function that was installed then, if any.
+        let f = second.ent_id(Key::from(":test/set")).unwrap();
+        let as_of = |db: &DatabaseSnapshot| second.as_of(&Time::TransactionId(db.basis_t()));
+        assert!(as_of(&before).function(f).is_none());
+        let installed = as_of(&first).function(f).unwrap()(&second, &[]);
+        assert_eq!(
+            installed,
+            Ok(vec![TxOp::add(E, STRING, V::String("a".to_string()))])
+        );
+
+        let retracted = second
+            .transact(vec![TxOp::retract_entity(f)])
+            .unwrap()
+            .db_after;
+        assert!(retracted.function(f).is_none());
+        let unknown = Identity::EntityId(f);
+        assert_eq!(
+            retracted
+                .transact(vec![TxOp::Call(unknown.clone(), vec![])])
+                .err(),
+            Some(Error::UnknownFunction(unknown))
+        );
+
+        assert_eq!(
+            second.install_fn(Key::from(":test/string"), set("c")).err(),
+            Some(Error::IdentInUse(Key::from(":test/string")))
+        );
+    }
+
+    #[test]
+    fn recursive_transaction_functions_are_refused() {
+        let call = |f: &str| TxOp::call(Key::from(f), vec![]);
+        let db = db()
+            .install_fn(Key::from(":test/ping"), move |_, _| {
+                Ok(vec![call(":test/pong")])
+            })
+            .unwrap()
+            .db_after
+            .install_fn(Key::from(":test/pong"), move |_, _| {
+                Ok(vec![call(":test/ping")])
+            })
+            .unwrap()
+            .db_after
+            .install_fn(Key::from(":test/again"), move |_, _| {
+                Ok(vec![TxOp::add(E, LONGS, V::I64(1)), call(":test/again")])
+            })
+            .unwrap()
+            .db_after;
+        for f in [":test/ping", ":test/again"] {
+            assert!(matches!(
+                db.transact(vec![call(f)]),
+                Err(Error::CallDepth(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn transactions_are_reified() {
+        let report = db()
+            .transact(vec![
+                TxOp::add(E, LONGS, V::I64(1)),
+                TxOp::add(EntityRef::Tx, STRING, V::String("import".to_string())),
+            ])
+            .unwrap();
+        let tx = partition::tx_entity(2);
+        let db = report.db_after;
+        assert_eq!(
+            db.select_eav(tx, STRING, &V::String("import".to_string()))
+                .count(),
+            1
+        );
+        assert_eq!(db.select_ea(tx, DB_TX_INSTANT).count(), 1);
+
+        let later = SystemTime::now() + Duration::from_secs(3600);
+        let db = db
+            .transact(vec![TxOp::add(
+                EntityRef::Tx,
+                DB_TX_INSTANT,
+                V::Instant(later),
+            )])
+            .unwrap()
+            .db_after;
+        assert_eq!(db.as_of(&Time::Instant(later)).as_of_t(), Some(3));
+        let earlier = later - Duration::from_secs(1);
+        assert_eq!(db.as_of(&Time::Instant(earlier)).as_of_t(), Some(2));
+        assert_eq!(
+            db.as_of(&Time::Instant(SystemTime::UNIX_EPOCH)).as_of_t(),
+            Some(0)
+        );
+
+        // the clock is behind the last transaction, which the next one can't precede
+        let report = db.transact(vec![]).unwrap();
+        let instant = V::Instant(later);
+        let tx = partition::tx_entity(4);
+        assert_eq!(
+            report.tx_data,
+            vec![Datom::new(tx, DB_TX_INSTANT, instant, 4, true)]
+        );
+        assert_eq!(
+            db.transact(vec![TxOp::add(
+                EntityRef::Tx,
+                DB_TX_INSTANT,
+                V::Instant(earlier)
+            )])
+            .err(),
+            Some(Error::TxInstant {
+                instant: earlier,
+                previous: later
+            })
+        );
+
+        // Other entities, earlier transactions' included, can't hold an instant.
+        let misplaced = |e| vec![TxOp::add(e, DB_TX_INSTANT, V::Instant(later))];
+        assert_eq!(
+            db.transact(misplaced(E)).err(),
+            Some(Error::TxInstantEntity(E))
+        );
+        let old = partition::tx_entity(2);
+        assert_eq!(
+            db.transact(misplaced(old)).err(),
+            Some(Error::TxInstantEntity(old))
+        );
+        let e = partition::entity_id(partition::PART_USER, 100);
+        let inserted = db
+            .clone()
+            .insert(Datom::new(e, DB_TX_INSTANT, V::Instant(later), 3, true));
+        assert_eq!(inserted.as_of(&Time::Instant(later)).as_of_t(), Some(3));
+    }
+}